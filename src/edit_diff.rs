@@ -17,10 +17,12 @@ pub enum EditDiff {
     Newline,
     InsertLine(usize, String),
     DeleteLine(usize, String),
+    Cursor(usize, usize),
 }
 
 impl EditDiff {
-    pub fn apply(&self, rows: &mut Vec<Row>, which: UndoRedo) -> (usize, usize) {
+    // New rows are rendered with `tab_stop`
+    pub fn apply(&self, rows: &mut Vec<Row>, which: UndoRedo, tab_stop: usize) -> (usize, usize) {
         // Returns cursor's next position (x, y)
         use UndoRedo::*;
         match *self {
@@ -76,7 +78,7 @@ impl EditDiff {
                     (x + s.chars().count(), y)
                 }
                 Undo => {
-                    rows[y].remove(x, s.chars().count());
+                    rows[y].remove(x, x + s.chars().count());
                     (x, y)
                 }
             },
@@ -94,7 +96,7 @@ impl EditDiff {
             },
             EditDiff::Newline => match which {
                 Redo => {
-                    rows.push(Row::empty());
+                    rows.push(Row::with_tab_stop("", tab_stop).unwrap());
                     (0, rows.len() - 1)
                 }
                 Undo => {
@@ -105,12 +107,15 @@ impl EditDiff {
             },
             EditDiff::InsertLine(y, ref s) => match which {
                 Redo => {
-                    rows.insert(y, Row::new(s).unwrap());
+                    rows.insert(y, Row::with_tab_stop(s, tab_stop).unwrap());
                     (0, y)
                 }
                 Undo => {
                     rows.remove(y);
-                    (rows[y - 1].len(), y - 1)
+                    match y.checked_sub(1) {
+                        Some(y) => (rows[y].len(), y),
+                        None => (0, 0),
+                    }
                 }
             },
             EditDiff::DeleteLine(y, ref s) => match which {
@@ -120,17 +125,22 @@ impl EditDiff {
                     } else {
                         rows.remove(y);
                     }
-                    (rows[y - 1].len(), y - 1)
+                    match y.checked_sub(1) {
+                        Some(y) => (rows[y].len(), y),
+                        None => (0, 0),
+                    }
                 }
                 Undo => {
                     if y == rows.len() {
-                        rows.push(Row::new(s).unwrap());
+                        rows.push(Row::with_tab_stop(s, tab_stop).unwrap());
                     } else {
-                        rows.insert(y, Row::new(s).unwrap());
+                        rows.insert(y, Row::with_tab_stop(s, tab_stop).unwrap());
                     }
                     (0, y)
                 }
             },
+            // Only moves the cursor. Useful to restore the cursor position on undo/redo
+            EditDiff::Cursor(x, y) => (x, y),
         }
     }
 }
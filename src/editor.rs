@@ -1,14 +1,74 @@
 use crate::error::Result;
 use crate::highlight::Highlighting;
 use crate::input::{InputSeq, KeySeq};
-use crate::language::Language;
+use crate::key_macro::{self, MacroInput};
+use crate::language::{Indent, IndentConfig, IndentSetting, Language};
 use crate::prompt::{self, Prompt, PromptResult};
+use crate::row::Row;
 use crate::screen::Screen;
+use crate::shell;
 use crate::status_bar::StatusBar;
-use crate::text_buffer::{CursorDir, Lines, TextBuffer};
+use crate::text_buffer::{CaseConversion, CursorDir, Lines, SortOrder, TextBuffer};
+use std::cmp;
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
 
+// Default column at which paragraphs are wrapped by filling
+pub const DEFAULT_FILL_COLUMN: usize = 80;
+
+// Avoid infinite loop when no search fails while replaying keyboard macro until search fails
+const MAX_MACRO_REPEAT: usize = 10000;
+
+// Limit of count given by Alt-<digit> prefix not to freeze the editor by repeating a command
+const MAX_REPEAT_COUNT: usize = MAX_MACRO_REPEAT;
+
+// Commands which prompt, run external commands, save or load files, or switch editor state are run
+// only once even if repeat count is given
+fn is_repeatable(seq: &InputSeq) -> bool {
+    match seq {
+        InputSeq {
+            key: KeySeq::Key(b),
+            alt: true,
+            ..
+        } => !b"xmrs|!zwg()ESLI".contains(b),
+        InputSeq {
+            key: KeySeq::Key(b),
+            ctrl: true,
+            ..
+        } => !b"gls o?xq".contains(b),
+        InputSeq { key, .. } => *key != KeySeq::InsertKey,
+    }
+}
+
+// Parse position to go to. Accepted formats are 'N', 'N:M' (line and column), '+N', '-N' (relative
+// to current line) and 'N%' (percentage of buffer). Returns 0-based line and column.
+fn parse_goto_position(
+    input: &str,
+    current_line: usize,
+    num_lines: usize,
+) -> Option<(usize, Option<usize>)> {
+    let input = input.trim();
+    let (line, col) = match input.split_once(':') {
+        Some((line, col)) => (line, Some(col.trim().parse::<usize>().ok()?)),
+        None => (input, None),
+    };
+    let line = line.trim();
+
+    let y = if let Some(n) = line.strip_prefix('+') {
+        current_line.saturating_add(n.parse().ok()?)
+    } else if let Some(n) = line.strip_prefix('-') {
+        current_line.saturating_sub(n.parse().ok()?)
+    } else if let Some(p) = line.strip_suffix('%') {
+        let percent = cmp::min(p.trim().parse::<usize>().ok()?, 100);
+        (num_lines * percent / 100).saturating_sub(1)
+    } else {
+        line.parse::<usize>().ok()?.saturating_sub(1)
+    };
+
+    Some((y, col.map(|c| c.saturating_sub(1))))
+}
+
 enum EditStep {
     Continue(InputSeq),
     Quit,
@@ -24,13 +84,29 @@ impl EditStep {
 }
 
 pub struct Editor<I: Iterator<Item = Result<InputSeq>>, W: Write> {
-    input: I,       // Escape sequences stream represented as Iterator
-    quitting: bool, // After first Ctrl-Q
+    input: MacroInput<I>, // Escape sequences stream represented as Iterator
+    quitting: bool,       // After first Ctrl-Q
     hl: Highlighting,
     screen: Screen<W>,
     bufs: Vec<TextBuffer>,
     buf_idx: usize,
     status_bar: StatusBar,
+    // Text of each line in the last killed rectangle
+    rectangle: Vec<String>,
+    // Keys of the last recorded keyboard macro
+    keyboard_macro: Vec<InputSeq>,
+    replaying_macro: bool,
+    // Set when text search failed or was canceled. This is used to stop replaying keyboard macro
+    search_failed: bool,
+    // Count given by Alt-<digit> prefix to repeat the next command
+    repeat_count: Option<usize>,
+    // Max width of lines filled by filling paragraph
+    fill_column: usize,
+    // Indentation and tab stop applied to opened buffers
+    indent_config: IndentConfig,
+    // Cursor positions (y, x) of buffers which are set before editing. Each position is applied
+    // when the buffer becomes current since the screen shows only the current buffer
+    pending_cursors: HashMap<usize, (usize, usize)>,
 }
 
 impl<I, W> Editor<I, W>
@@ -47,13 +123,21 @@ where
         let screen = Screen::new(window_size, &mut input, output)?;
         let status_bar = StatusBar::from_buffer(&buf, (1, 1));
         Ok(Editor {
-            input,
+            input: MacroInput::new(input),
             quitting: false,
             hl: Highlighting::default(),
             screen,
             bufs: vec![buf],
             buf_idx: 0,
             status_bar,
+            rectangle: vec![],
+            keyboard_macro: vec![],
+            replaying_macro: false,
+            search_failed: false,
+            repeat_count: None,
+            fill_column: DEFAULT_FILL_COLUMN,
+            indent_config: IndentConfig::default(),
+            pending_cursors: HashMap::new(),
         })
     }
 
@@ -84,16 +168,39 @@ where
         let hl = Highlighting::new(bufs[0].lang(), bufs[0].rows());
         let status_bar = StatusBar::from_buffer(&bufs[0], (1, bufs.len()));
         Ok(Editor {
-            input,
+            input: MacroInput::new(input),
             quitting: false,
             hl,
             screen,
             bufs,
             buf_idx: 0,
             status_bar,
+            rectangle: vec![],
+            keyboard_macro: vec![],
+            replaying_macro: false,
+            search_failed: false,
+            repeat_count: None,
+            fill_column: DEFAULT_FILL_COLUMN,
+            indent_config: IndentConfig::default(),
+            pending_cursors: HashMap::new(),
         })
     }
 
+    pub fn set_fill_column(&mut self, column: usize) {
+        self.fill_column = column;
+    }
+
+    // Set indentation and tab stop configured globally or per language. They are applied to all
+    // opened buffers and buffers opened later
+    pub fn set_indent_config(&mut self, config: IndentConfig) {
+        for buf in self.bufs.iter_mut() {
+            buf.set_default_indent_setting(config.setting(buf.lang()));
+        }
+        self.indent_config = config;
+        self.hl.needs_update = true;
+        self.screen.set_dirty_start(0);
+    }
+
     pub fn buf(&self) -> &TextBuffer {
         &self.bufs[self.buf_idx]
     }
@@ -106,10 +213,12 @@ where
         self.status_bar
             .set_buf_pos((self.buf_idx + 1, self.bufs.len()));
         self.status_bar.update_from_buf(&self.bufs[self.buf_idx]);
+        self.status_bar.set_recording(self.input.is_recording());
     }
 
     fn render_screen(&mut self) -> Result<()> {
         self.refresh_status_bar();
+        self.screen.set_overwrite_cursor(self.buf().overwrite())?;
         self.screen
             .render(&self.bufs[self.buf_idx], &mut self.hl, &self.status_bar)?;
         self.status_bar.redraw = false;
@@ -133,11 +242,12 @@ where
             "Open: {} (Empty name for new text buffer, ^G or ESC to cancel)",
             false,
         )? {
-            let buf = if input.is_empty() {
+            let mut buf = if input.is_empty() {
                 TextBuffer::empty()
             } else {
                 TextBuffer::open(input)?
             };
+            buf.set_default_indent_setting(self.indent_config.setting(buf.lang()));
             self.hl = Highlighting::new(buf.lang(), buf.rows());
             self.bufs.push(buf);
             self.buf_idx = self.bufs.len() - 1;
@@ -161,6 +271,9 @@ where
         // Then we don't need to recreate Highlighting instance for each buffer switch.
         self.hl = Highlighting::new(buf.lang(), buf.rows());
         self.will_reset_scroll();
+        if let Some((y, x)) = self.pending_cursors.remove(&idx) {
+            self.move_cursor_to(y, x);
+        }
     }
 
     fn next_buffer(&mut self) {
@@ -201,6 +314,8 @@ where
             if let PromptResult::Input(input) = self.prompt::<prompt::NoAction>(template, true)? {
                 let prev_lang = self.buf().lang();
                 self.buf_mut().set_file(input);
+                let setting = self.indent_config.setting(self.buf().lang());
+                self.buf_mut().set_default_indent_setting(setting);
                 self.hl.lang_changed(self.buf().lang());
                 if prev_lang != self.buf().lang() {
                     // Render entire screen since highglight updated
@@ -225,10 +340,85 @@ where
 
     fn find(&mut self) -> Result<()> {
         let template = "Search: {} (^F or ^N or RIGHT to forward, ^B or ^P or LEFT to back, ^G or ESC to cancel)";
-        self.prompt::<prompt::TextSearch>(template, true)?;
+        if self.prompt::<prompt::TextSearch>(template, true)? == PromptResult::Canceled {
+            self.search_failed = true; // Canceled or not found
+        }
+        Ok(())
+    }
+
+    fn go_to_line(&mut self) -> Result<()> {
+        let template = "Go to line: {} (N, N:COL, +N, -N or N%, ^G or ESC to cancel)";
+        let input = match self.prompt::<prompt::NoAction>(template, true)? {
+            PromptResult::Input(input) => input,
+            PromptResult::Canceled => return Ok(()),
+        };
+
+        let buf = self.buf();
+        let num_lines = buf.rows().len();
+        let (y, x) = match parse_goto_position(&input, buf.cy(), num_lines) {
+            Some(pos) => pos,
+            None => {
+                self.screen
+                    .set_error_message(format!("Invalid line position '{}'", input));
+                return Ok(());
+            }
+        };
+        self.move_cursor_to(y, x.unwrap_or(0));
+        Ok(())
+    }
+
+    fn set_buffer_indent(&mut self) -> Result<()> {
+        let template =
+            "Indent: {} ('tab' or N spaces, optionally ':TAB_STOP', ^G or ESC to cancel)";
+        let input = match self.prompt::<prompt::NoAction>(template, true)? {
+            PromptResult::Input(input) => input,
+            PromptResult::Canceled => return Ok(()),
+        };
+
+        let setting = match IndentSetting::parse(&input) {
+            Some(setting) => setting,
+            None => {
+                self.screen
+                    .set_error_message(format!("Invalid indentation '{}'", input));
+                return Ok(());
+            }
+        };
+        self.buf_mut().set_indent_setting(setting);
+        let buf = self.buf();
+        let indent = match buf.indent() {
+            Indent::AsIs => "tab".to_string(),
+            Indent::Spaces(width) => format!("{} spaces", width),
+        };
+        let msg = format!("Indent: {}, tab stop: {}", indent, buf.tab_stop());
+        self.screen.set_info_message(msg);
         Ok(())
     }
 
+    // Move cursor to the position clamped in the current buffer and put the line at center of
+    // screen as text search does
+    fn move_cursor_to(&mut self, y: usize, x: usize) {
+        let rows = self.buf().rows();
+        let y = cmp::min(y, rows.len().saturating_sub(1));
+        let x = cmp::min(x, rows.get(y).map(Row::len).unwrap_or(0));
+        self.buf_mut().set_cursor(x, y);
+
+        self.screen.rowoff = y.saturating_sub(self.screen.rows() / 2);
+        self.screen.coloff = 0;
+        self.screen.set_dirty_start(self.screen.rowoff);
+    }
+
+    // Set cursor position of the buffer at `buf_idx` before starting to edit. Line and column are
+    // 1-based as positions in outputs of compilers. Out of range position is clamped.
+    pub fn set_cursor_position(&mut self, buf_idx: usize, line: usize, col: usize) {
+        let pos = (line.saturating_sub(1), col.saturating_sub(1));
+        if buf_idx == self.buf_idx {
+            self.move_cursor_to(pos.0, pos.1);
+        } else {
+            // Scroll position of the current buffer must not be changed
+            self.pending_cursors.insert(buf_idx, pos);
+        }
+    }
+
     fn show_help(&mut self) -> Result<()> {
         self.screen.render_help()?;
 
@@ -248,6 +438,351 @@ where
         Ok(())
     }
 
+    // Show the message and read the next key of prefixed command such as Alt-R
+    fn read_command_key(&mut self, message: &str) -> Result<Option<InputSeq>> {
+        self.screen.set_info_message(message);
+        self.render_screen()?;
+
+        let mut seq = None;
+        for s in &mut self.input {
+            let s = s?;
+            if s.key != KeySeq::Unidentified {
+                seq = Some(s);
+                break;
+            }
+        }
+        self.screen.unset_message();
+        Ok(seq)
+    }
+
+    fn rectangle_command(&mut self) -> Result<()> {
+        let seq = match self.read_command_key(
+            "Rectangle: k (kill), y (yank), i (insert text), t (replace with text)",
+        )? {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+
+        match seq.key {
+            KeySeq::Key(b'k') => match self.buf_mut().kill_rectangle() {
+                Some(rect) => self.rectangle = rect,
+                None => self.screen.set_error_message("Mark is not set"),
+            },
+            KeySeq::Key(b'y') if self.rectangle.is_empty() => {
+                self.screen.set_error_message("No rectangle was killed")
+            }
+            KeySeq::Key(b'y') => self.bufs[self.buf_idx].yank_rectangle(&self.rectangle),
+            KeySeq::Key(c @ b'i') | KeySeq::Key(c @ b't') => {
+                let replace = c == b't';
+                let template = if replace {
+                    "Replace rectangle with: {} (^G or ESC to cancel)"
+                } else {
+                    "Insert on each line: {} (^G or ESC to cancel)"
+                };
+                if let PromptResult::Input(text) =
+                    self.prompt::<prompt::NoAction>(template, false)?
+                {
+                    if !self.buf_mut().insert_rectangle_text(&text, replace) {
+                        self.screen.set_error_message("Mark is not set");
+                    }
+                }
+            }
+            _ => self
+                .screen
+                .set_error_message(format!("Key '{}' is not a rectangle command", seq)),
+        }
+        Ok(())
+    }
+
+    fn lines_command(&mut self) -> Result<()> {
+        let seq = match self.read_command_key(
+            "Lines in region: s/S (sort), n/N (numeric sort), i/I (ignore case sort), u (uniq), r (reverse)",
+        )? {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+
+        let buf = self.buf_mut();
+        let done = match seq.key {
+            KeySeq::Key(b's') => buf.sort_lines(SortOrder::Lexical, false),
+            KeySeq::Key(b'S') => buf.sort_lines(SortOrder::Lexical, true),
+            KeySeq::Key(b'n') => buf.sort_lines(SortOrder::Numeric, false),
+            KeySeq::Key(b'N') => buf.sort_lines(SortOrder::Numeric, true),
+            KeySeq::Key(b'i') => buf.sort_lines(SortOrder::IgnoreCase, false),
+            KeySeq::Key(b'I') => buf.sort_lines(SortOrder::IgnoreCase, true),
+            KeySeq::Key(b'u') => buf.uniq_lines(),
+            KeySeq::Key(b'r') => buf.reverse_lines(),
+            _ => {
+                self.screen
+                    .set_error_message(format!("Key '{}' is not a lines command", seq));
+                return Ok(());
+            }
+        };
+        if !done {
+            self.screen.set_error_message("Mark is not set");
+        }
+        Ok(())
+    }
+
+    fn zap_to_char(&mut self) -> Result<()> {
+        let seq = match self.read_command_key("Zap to char: ")? {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+        let ch = match seq {
+            InputSeq {
+                key: KeySeq::Key(b),
+                ctrl: false,
+                alt: false,
+            } if !b.is_ascii_control() => b as char,
+            InputSeq {
+                key: KeySeq::Utf8Key(c),
+                ctrl: false,
+                alt: false,
+            } => c,
+            _ => return Ok(()), // Canceled by other keys such as ^G
+        };
+        if !self.buf_mut().zap_to_char(ch) {
+            self.screen
+                .set_error_message(format!("Character '{}' not found", ch));
+        }
+        Ok(())
+    }
+
+    fn filter_through_command(&mut self) -> Result<()> {
+        let template = if self.buf().has_mark() {
+            "Filter region through command: {} (^G or ESC to cancel)"
+        } else {
+            "Filter buffer through command: {} (^G or ESC to cancel)"
+        };
+        let command = match self.prompt::<prompt::NoAction>(template, true)? {
+            PromptResult::Input(command) => command,
+            PromptResult::Canceled => return Ok(()),
+        };
+
+        let (lines, text) = self.buf_mut().lines_to_filter();
+        let running = match shell::Running::spawn_with_input(&command, text) {
+            Ok(running) => running,
+            Err(msg) => {
+                self.screen.set_error_message(msg);
+                return Ok(());
+            }
+        };
+        // Buffer is not modified while waiting since typed keys are handled after the command
+        match self.wait_for_command(running, &command)? {
+            Some(Ok((stdout, warning))) => {
+                self.buf_mut().replace_filtered_lines(lines, &stdout);
+                if let Some(msg) = warning {
+                    self.screen.set_info_message(msg);
+                }
+            }
+            Some(Err(msg)) => self.screen.set_error_message(msg),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn insert_command_output(&mut self) -> Result<()> {
+        let command = match self.prompt::<prompt::NoAction>(
+            "Insert output of command: {} (^G or ESC to cancel)",
+            true,
+        )? {
+            PromptResult::Input(command) => command,
+            PromptResult::Canceled => return Ok(()),
+        };
+        let running = match shell::Running::spawn(&command) {
+            Ok(running) => running,
+            Err(msg) => {
+                self.screen.set_error_message(msg);
+                return Ok(());
+            }
+        };
+
+        match self.wait_for_command(running, &command)? {
+            Some(Ok((stdout, warning))) => {
+                // Trailing newline is not inserted like $(...) in shell
+                let text = stdout.strip_suffix('\n').unwrap_or(&stdout);
+                self.buf_mut().insert_str(text);
+                if let Some(msg) = warning {
+                    self.screen.set_info_message(msg);
+                }
+            }
+            Some(Err(msg)) => self.screen.set_error_message(msg),
+            None => {}
+        }
+        Ok(())
+    }
+
+    // Wait for the command while redrawing screen. Keys typed while the command is running are
+    // handled after it finished. Returns None when the command was canceled with ^G
+    fn wait_for_command(
+        &mut self,
+        mut running: shell::Running,
+        command: &str,
+    ) -> Result<Option<shell::Output>> {
+        self.screen
+            .set_info_message(format!("Running '{}'... (^G to cancel)", command));
+        let mut typed = vec![];
+        while !running.is_finished() {
+            self.render_screen()?;
+            let seq = match self.input.next() {
+                Some(seq) => seq,
+                None => break, // Wait for the command when no more input is given
+            };
+            if self.screen.maybe_resize(&mut self.input)? {
+                self.will_reset_screen();
+            }
+            let seq = match seq {
+                Ok(seq) => seq,
+                Err(err) => {
+                    running.kill();
+                    return Err(err);
+                }
+            };
+            match seq {
+                InputSeq {
+                    key: KeySeq::Key(b'g'),
+                    ctrl: true,
+                    ..
+                } => {
+                    running.kill();
+                    self.input.unread(typed);
+                    self.screen
+                        .set_info_message(format!("Canceled running '{}'", command));
+                    return Ok(None);
+                }
+                InputSeq {
+                    key: KeySeq::Unidentified,
+                    ..
+                } => {}
+                seq => typed.push(seq),
+            }
+        }
+        self.input.unread(typed);
+        self.screen.unset_message();
+        Ok(Some(running.finish()))
+    }
+
+    fn start_macro(&mut self) {
+        if self.input.is_recording() {
+            self.screen
+                .set_error_message("Already recording keyboard macro");
+            return;
+        }
+        self.input.start_recording();
+        self.screen.set_info_message("Recording keyboard macro...");
+    }
+
+    fn stop_macro(&mut self) {
+        let mut keys = if let Some(keys) = self.input.stop_recording() {
+            keys
+        } else {
+            self.screen
+                .set_error_message("Not recording keyboard macro");
+            return;
+        };
+        keys.pop(); // Remove the key to stop recording
+        if keys.is_empty() {
+            self.screen.set_error_message("Keyboard macro is empty");
+            return;
+        }
+        self.screen
+            .set_info_message(format!("Keyboard macro recorded ({} keys)", keys.len()));
+        self.keyboard_macro = keys;
+    }
+
+    fn replay_macro_keys(&mut self, count: usize) -> Result<bool> {
+        for _ in 0..count {
+            self.input.replay(self.keyboard_macro.iter().cloned());
+            while let Some(seq) = self.input.next_replayed() {
+                if !self.process_keypress(seq)?.continues() {
+                    return Ok(true);
+                }
+                if self.search_failed {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // Replay the keyboard macro through process_keypress `count` times. When count is None, it is
+    // repeated until search fails. Replaying also stops when search fails while repeating N times.
+    // Returns true when replayed keys quit the editor.
+    fn replay_macro(&mut self, count: Option<usize>) -> Result<bool> {
+        if self.replaying_macro || self.input.is_recording() {
+            self.screen
+                .set_error_message("Cannot replay keyboard macro while recording or replaying");
+            return Ok(false);
+        }
+        if self.keyboard_macro.is_empty() {
+            self.screen
+                .set_error_message("No keyboard macro was recorded");
+            return Ok(false);
+        }
+
+        self.replaying_macro = true;
+        self.search_failed = false;
+        let result = self.replay_macro_keys(count.unwrap_or(MAX_MACRO_REPEAT));
+        self.input.cancel_replay();
+        self.replaying_macro = false;
+        result
+    }
+
+    fn replay_macro_repeatedly(&mut self) -> Result<bool> {
+        let template =
+            "Replay macro N times: {} (Empty to repeat until search fails, ^G or ESC to cancel)";
+        let input = match self.prompt::<prompt::NoAction>(template, false)? {
+            PromptResult::Input(input) => input,
+            PromptResult::Canceled => return Ok(false),
+        };
+        if input.is_empty() {
+            return self.replay_macro(None);
+        }
+        match input.trim().parse() {
+            Ok(count) => self.replay_macro(Some(count)),
+            Err(_) => {
+                self.screen
+                    .set_error_message(format!("'{}' is not a number", input));
+                Ok(false)
+            }
+        }
+    }
+
+    fn save_macro(&mut self) -> Result<()> {
+        if self.keyboard_macro.is_empty() {
+            self.screen
+                .set_error_message("No keyboard macro was recorded");
+            return Ok(());
+        }
+        let template = "Save macro to: {} (^G or ESC to cancel)";
+        if let PromptResult::Input(path) = self.prompt::<prompt::NoAction>(template, true)? {
+            match key_macro::save(path, &self.keyboard_macro) {
+                Ok(msg) => self.screen.set_info_message(msg),
+                Err(msg) => self.screen.set_error_message(msg),
+            }
+        }
+        Ok(())
+    }
+
+    fn load_macro(&mut self) -> Result<()> {
+        let template = "Load macro from: {} (^G or ESC to cancel)";
+        if let PromptResult::Input(path) = self.prompt::<prompt::NoAction>(template, true)? {
+            match key_macro::load(path) {
+                Ok(keys) if keys.is_empty() => {
+                    self.screen.set_error_message("Keyboard macro is empty")
+                }
+                Ok(keys) => {
+                    self.screen
+                        .set_info_message(format!("Loaded keyboard macro ({} keys)", keys.len()));
+                    self.keyboard_macro = keys;
+                }
+                Err(msg) => self.screen.set_error_message(msg),
+            }
+        }
+        Ok(())
+    }
+
     fn handle_quit(&mut self, s: InputSeq) -> Result<EditStep> {
         let modified = self.bufs.iter().any(|b| b.modified());
         if !modified || self.quitting {
@@ -261,61 +796,190 @@ where
         }
     }
 
+    // Apply the operation to the text buffer at every cursor
+    fn each_cursor<F: FnMut(&mut TextBuffer)>(&mut self, f: F) {
+        self.bufs[self.buf_idx].for_each_cursor(f);
+    }
+
+    // Move cursors up or down. In soft wrap mode, they move by screen line
+    fn move_cursor_vertically(&mut self, dir: CursorDir) {
+        if !self.screen.soft_wrap() {
+            return self.each_cursor(|b| b.move_cursor_one(dir));
+        }
+        let screen = &self.screen;
+        self.bufs[self.buf_idx].for_each_cursor(|b| {
+            let (x, y) = screen.move_cursor_wrapped(b.rows(), b.cursor(), dir);
+            b.set_cursor(x, y);
+        });
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let hl = &self.hl;
+        self.bufs[self.buf_idx].for_each_cursor(|buf| {
+            if buf.overwrite() {
+                return buf.overwrite_char(c);
+            }
+            // Highlights are not updated while editing at each cursor. It is OK since cursors are
+            // visited from the bottom and lines before the cursor are not modified yet
+            let in_literal = hl.is_literal_at(buf.rows(), buf.cursor());
+            buf.insert_char_auto_pair(c, !in_literal);
+        });
+    }
+
+    fn delete_char(&mut self) {
+        let hl = &self.hl;
+        self.bufs[self.buf_idx].for_each_cursor(|buf| {
+            // Check the position before the deleted character since it may be an opening quote
+            let (x, y) = buf.cursor();
+            let in_literal = hl.is_literal_at(buf.rows(), (x.saturating_sub(1), y));
+            buf.delete_char(!in_literal);
+        });
+    }
+
+    fn jump_to_matching_bracket(&mut self) {
+        let buf = &self.bufs[self.buf_idx];
+        // Highlight entire buffer to skip brackets in string literals and comments
+        let lines = 0..buf.rows().len();
+        self.hl.update(buf.rows(), lines.end);
+        match self.hl.find_bracket_pair(buf.rows(), buf.cursor(), lines) {
+            Some([_, (x, y)]) => self.buf_mut().set_cursor(x, y),
+            None => self.screen.set_error_message("No matching bracket"),
+        }
+    }
+
     fn handle_not_mapped(&mut self, seq: &InputSeq) {
         self.screen
             .set_error_message(format!("Key '{}' not mapped", seq));
     }
 
-    fn process_keypress(&mut self, s: InputSeq) -> Result<EditStep> {
+    // Run the command mapped to the key. Returns a step when the key input should finish
+    // immediately without the following process
+    fn dispatch_key(&mut self, s: &InputSeq) -> Result<Option<EditStep>> {
         use KeySeq::*;
 
         let rowoff = self.screen.rowoff;
         let rows = self.screen.rows();
-        let prev_cursor = self.buf().cursor();
 
-        match &s {
+        match s {
             InputSeq {
                 key: Unidentified, ..
-            } => return Ok(EditStep::Continue(s)),
+            } => return Ok(Some(EditStep::Continue(s.clone()))),
             InputSeq { key, alt: true, .. } => match key {
                 Key(b'v') => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
-                Key(b'f') => self.buf_mut().move_cursor_by_word(CursorDir::Right),
-                Key(b'b') => self.buf_mut().move_cursor_by_word(CursorDir::Left),
+                Key(b'f') => self.each_cursor(|b| b.move_cursor_by_word(CursorDir::Right)),
+                Key(b'b') => self.each_cursor(|b| b.move_cursor_by_word(CursorDir::Left)),
                 Key(b'n') => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
                 Key(b'p') => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
                 Key(b'x') => self.previous_buffer(),
+                Key(b'm') => self.jump_to_matching_bracket(),
+                Key(b'r') => self.rectangle_command()?,
+                Key(b's') => self.lines_command()?,
+                Key(b'|') => self.filter_through_command()?,
+                Key(b'!') => self.insert_command_output()?,
+                Key(b'd') => self.each_cursor(TextBuffer::delete_word_forward),
+                Key(0x7f) => self.each_cursor(TextBuffer::delete_word_backward),
+                Key(b'k') => self.each_cursor(TextBuffer::delete_whole_line),
+                Key(b'z') => self.zap_to_char()?,
+                Key(b'w') => {
+                    if self.screen.toggle_soft_wrap() {
+                        self.screen.set_info_message("Soft wrap enabled");
+                    } else {
+                        self.screen.set_info_message("Soft wrap disabled");
+                    }
+                }
+                Key(b'g') => self.go_to_line()?,
+                Key(b'(') => self.start_macro(),
+                Key(b')') => self.stop_macro(),
+                Key(b'e') => {
+                    if self.replay_macro(Some(1))? {
+                        return Ok(Some(EditStep::Quit));
+                    }
+                }
+                Key(b'E') => {
+                    if self.replay_macro_repeatedly()? {
+                        return Ok(Some(EditStep::Quit));
+                    }
+                }
+                Key(b'S') => self.save_macro()?,
+                Key(b'L') => self.load_macro()?,
+                Key(b't') => self.buf_mut().transpose_words(),
+                Key(b'u') => self.buf_mut().convert_case(CaseConversion::Upcase),
+                Key(b'l') => self.buf_mut().convert_case(CaseConversion::Downcase),
+                Key(b'c') => self.buf_mut().convert_case(CaseConversion::Capitalize),
+                Key(b'y') => self.buf_mut().duplicate_lines(),
+                Key(b'q') => {
+                    let width = self.fill_column;
+                    self.buf_mut().fill_paragraph(Some(width));
+                }
+                Key(b'Q') => self.buf_mut().fill_paragraph(None),
+                UpKey => self.buf_mut().move_lines(CursorDir::Up),
+                DownKey => self.buf_mut().move_lines(CursorDir::Down),
+                Key(b'j') => {
+                    if !self.buf_mut().add_cursor_at_next_word() {
+                        self.screen
+                            .set_error_message("No other occurrence of word at cursor");
+                    }
+                }
+                Key(b'a') => {
+                    if !self.buf_mut().add_cursors_on_region_lines() {
+                        self.screen
+                            .set_error_message("No line to add cursor in region");
+                    }
+                }
+                Key(b'o') => {
+                    if self.buf_mut().clear_secondary_cursors() {
+                        self.screen.set_info_message("Secondary cursors removed");
+                    }
+                }
+                Key(b'i') => self.buf_mut().reindent(),
+                Key(b'I') => self.set_buffer_indent()?,
+                Key(b';') => {
+                    if !self.buf_mut().toggle_comment() {
+                        let lang = self.buf().lang().name();
+                        self.screen
+                            .set_error_message(format!("No comment syntax for {}", lang));
+                    }
+                }
                 Key(b'<') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Up),
                 Key(b'>') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down),
-                LeftKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                RightKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                _ => self.handle_not_mapped(&s),
+                LeftKey => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Left)),
+                RightKey => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Right)),
+                _ => self.handle_not_mapped(s),
             },
             InputSeq {
                 key, ctrl: true, ..
             } => match key {
-                Key(b'p') => self.buf_mut().move_cursor_one(CursorDir::Up),
-                Key(b'b') => self.buf_mut().move_cursor_one(CursorDir::Left),
-                Key(b'n') => self.buf_mut().move_cursor_one(CursorDir::Down),
-                Key(b'f') => self.buf_mut().move_cursor_one(CursorDir::Right),
+                Key(b'p') => self.move_cursor_vertically(CursorDir::Up),
+                Key(b'b') => self.each_cursor(|b| b.move_cursor_one(CursorDir::Left)),
+                Key(b'n') => self.move_cursor_vertically(CursorDir::Down),
+                Key(b'f') => self.each_cursor(|b| b.move_cursor_one(CursorDir::Right)),
                 Key(b'v') => self
                     .buf_mut()
                     .move_cursor_page(CursorDir::Down, rowoff, rows),
-                Key(b'a') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                Key(b'e') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                Key(b'd') => self.buf_mut().delete_right_char(),
+                Key(b'a') => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Left)),
+                Key(b'e') => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Right)),
+                Key(b'd') => self.each_cursor(TextBuffer::delete_right_char),
                 Key(b'g') => self.find()?,
-                Key(b'h') => self.buf_mut().delete_char(),
-                Key(b'k') => self.buf_mut().delete_until_end_of_line(),
-                Key(b'j') => self.buf_mut().delete_until_head_of_line(),
-                Key(b'w') => self.buf_mut().delete_word(),
+                Key(b'h') => self.delete_char(),
+                Key(b'k') => self.each_cursor(TextBuffer::delete_until_end_of_line),
+                Key(b'j') => self.each_cursor(TextBuffer::delete_until_head_of_line),
+                Key(b'w') => self.each_cursor(TextBuffer::delete_word),
                 Key(b'l') => {
                     self.screen.set_dirty_start(self.screen.rowoff); // Clear
                     self.screen.unset_message();
                     self.status_bar.redraw = true;
                 }
                 Key(b's') => self.save()?,
-                Key(b'i') => self.buf_mut().insert_tab(),
-                Key(b'm') => self.buf_mut().insert_line(),
+                Key(b' ') => {
+                    if self.buf_mut().toggle_mark() {
+                        self.screen.set_info_message("Mark set");
+                    } else {
+                        self.screen.set_info_message("Mark deactivated");
+                    }
+                }
+                Key(b'i') => self.each_cursor(TextBuffer::insert_tab),
+                Key(b't') => self.each_cursor(TextBuffer::transpose_chars),
+                Key(b'm') => self.each_cursor(TextBuffer::insert_line),
                 Key(b'o') => self.open_buffer()?,
                 Key(b'?') => self.show_help()?,
                 Key(b'x') => self.next_buffer(),
@@ -332,36 +996,93 @@ where
                         self.screen.set_info_message("Buffer is already newest");
                     }
                 }
-                LeftKey => self.buf_mut().move_cursor_by_word(CursorDir::Left),
-                RightKey => self.buf_mut().move_cursor_by_word(CursorDir::Right),
+                LeftKey => self.each_cursor(|b| b.move_cursor_by_word(CursorDir::Left)),
+                RightKey => self.each_cursor(|b| b.move_cursor_by_word(CursorDir::Right)),
                 DownKey => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
                 UpKey => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
-                Key(b'q') => return self.handle_quit(s),
-                _ => self.handle_not_mapped(&s),
+                Key(b'q') => return self.handle_quit(s.clone()).map(Some),
+                _ => self.handle_not_mapped(s),
             },
             InputSeq { key, .. } => match key {
                 Key(0x1b) => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows), // Clash with Ctrl-[
-                Key(0x08) => self.buf_mut().delete_char(), // Backspace
-                Key(0x7f) => self.buf_mut().delete_char(), // Delete key is mapped to \x1b[3~
-                Key(b'\r') => self.buf_mut().insert_line(),
-                Key(b) if !b.is_ascii_control() => self.buf_mut().insert_char(*b as char),
-                Utf8Key(c) => self.buf_mut().insert_char(*c),
-                UpKey => self.buf_mut().move_cursor_one(CursorDir::Up),
-                LeftKey => self.buf_mut().move_cursor_one(CursorDir::Left),
-                DownKey => self.buf_mut().move_cursor_one(CursorDir::Down),
-                RightKey => self.buf_mut().move_cursor_one(CursorDir::Right),
+                Key(0x08) => self.delete_char(), // Backspace
+                Key(0x7f) => self.delete_char(), // Delete key is mapped to \x1b[3~
+                Key(b'\r') => self.each_cursor(TextBuffer::insert_line),
+                Key(b) if !b.is_ascii_control() => self.insert_char(*b as char),
+                Utf8Key(c) => self.insert_char(*c),
+                UpKey => self.move_cursor_vertically(CursorDir::Up),
+                LeftKey => self.each_cursor(|b| b.move_cursor_one(CursorDir::Left)),
+                DownKey => self.move_cursor_vertically(CursorDir::Down),
+                RightKey => self.each_cursor(|b| b.move_cursor_one(CursorDir::Right)),
                 PageUpKey => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
                 PageDownKey => self
                     .buf_mut()
                     .move_cursor_page(CursorDir::Down, rowoff, rows),
-                HomeKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                EndKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                DeleteKey => self.buf_mut().delete_right_char(),
+                HomeKey => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Left)),
+                EndKey => self.each_cursor(|b| b.move_cursor_to_buffer_edge(CursorDir::Right)),
+                DeleteKey => self.each_cursor(TextBuffer::delete_right_char),
+                InsertKey => {
+                    if self.buf_mut().toggle_overwrite() {
+                        self.screen.set_info_message("Overwrite mode enabled");
+                    } else {
+                        self.screen.set_info_message("Overwrite mode disabled");
+                    }
+                }
+                Paste(text) => self.each_cursor(|b| b.insert_str(text)),
                 Cursor(_, _) => unreachable!(),
-                _ => self.handle_not_mapped(&s),
+                _ => self.handle_not_mapped(s),
             },
         }
 
+        Ok(None)
+    }
+
+    fn process_keypress(&mut self, s: InputSeq) -> Result<EditStep> {
+        if let InputSeq {
+            key: KeySeq::Key(b @ b'0'..=b'9'),
+            alt: true,
+            ctrl: false,
+        } = s
+        {
+            // Alt-<digit> prefix to repeat the next command
+            let digit = (b - b'0') as usize;
+            let count = self.repeat_count.unwrap_or(0) * 10 + digit;
+            let count = cmp::min(count, MAX_REPEAT_COUNT);
+            self.repeat_count = Some(count);
+            self.screen
+                .set_info_message(format!("Repeat next command {} times", count));
+            return Ok(EditStep::Continue(s));
+        }
+
+        if matches!(s.key, KeySeq::Unidentified | KeySeq::Cursor(..)) {
+            // Timeout ticks while idle and pseudo keys must not consume repeat count
+            return Ok(EditStep::Continue(s));
+        }
+
+        let count = match self.repeat_count.take() {
+            Some(count) => {
+                self.screen.unset_message();
+                // Count 0 is the same as no count
+                if count > 0 && is_repeatable(&s) {
+                    count
+                } else {
+                    1
+                }
+            }
+            None => 1,
+        };
+
+        let prev_cursor = self.buf().cursor();
+        let prev_cursors = self.buf().secondary_cursors().to_vec();
+
+        // Repeated command is recorded as one undo entry since undo point is not inserted until
+        // finish_edit() is called
+        for _ in 0..count {
+            if let Some(step) = self.dispatch_key(&s)? {
+                return Ok(step);
+            }
+        }
+
         if let Some(line) = self.buf_mut().finish_edit() {
             self.hl.needs_update = true;
             self.screen.set_dirty_start(line);
@@ -369,6 +1090,15 @@ where
         if self.buf().cursor() != prev_cursor {
             self.screen.cursor_moved = true;
         }
+        if self.buf().secondary_cursors() != prev_cursors.as_slice() {
+            // Redraw lines where secondary cursors were or are rendered
+            let cursors = prev_cursors
+                .iter()
+                .chain(self.buf().secondary_cursors().iter());
+            if let Some(line) = cursors.map(|&(_, y)| y).min() {
+                self.screen.set_dirty_start(line);
+            }
+        }
         self.quitting = false;
         Ok(EditStep::Continue(s))
     }
@@ -466,12 +1196,15 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::editor::Editor;
+    use crate::editor::{Editor, MAX_REPEAT_COUNT};
     use crate::error::Result;
+    use crate::highlight::Highlight;
     use crate::input::{InputSeq, KeySeq};
-    use crate::language::Language;
-    use std::fs::File;
+    use crate::language::{IndentConfig, Language};
+    use std::env;
+    use std::fs::{self, File};
     use std::io::{self, BufRead, BufReader, Write};
+    use std::process;
 
     use KeySeq::*;
 
@@ -509,10 +1242,12 @@ mod tests {
         InputSeq::ctrl(Key(c as u8))
     }
 
+    fn alt(c: char) -> InputSeq {
+        InputSeq::alt(Key(c as u8))
+    }
+
     fn sp(k: KeySeq) -> InputSeq {
-        if let Key(_) = k {
-            assert!(false, "{:?}", k);
-        }
+        assert!(!matches!(k, Key(_)), "{:?}", k);
         InputSeq::new(k)
     }
 
@@ -520,6 +1255,10 @@ mod tests {
         InputSeq::new(Utf8Key(c))
     }
 
+    fn paste(s: &str) -> InputSeq {
+        InputSeq::new(Paste(s.to_string()))
+    }
+
     #[test]
     fn empty_buffer() {
         let input = DummyInputs(vec![InputSeq::ctrl(Key(b'q'))]);
@@ -622,6 +1361,41 @@ mod tests {
         assert_eq!(msg, "");
     }
 
+    #[test]
+    fn undo_indent_inserted_in_middle_of_line() {
+        let input = DummyInputs(vec![sp(RightKey), ctrl('i'), ctrl('u')]);
+        let mut editor = Editor::with_lines(["ab"].iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.set_lang(Language::Rust); // Indent with spaces
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ab"]);
+        assert_eq!(editor.buf().cursor(), (1, 0));
+    }
+
+    #[test]
+    fn undo_redo_move_lines() {
+        let run = |dir: KeySeq, redo: bool| {
+            let mut keys = vec![sp(DownKey), sp(RightKey), InputSeq::alt(dir), ctrl('u')];
+            if redo {
+                keys.push(ctrl('r'));
+            }
+            let input = DummyInputs(keys);
+            let lines = ["ab", "cd", "ef"];
+            let mut editor =
+                Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+            editor.edit().unwrap();
+            editor
+        };
+
+        for (dir, moved) in [(UpKey, 0), (DownKey, 2)] {
+            let editor = run(dir.clone(), false);
+            assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ab", "cd", "ef"]);
+            assert_eq!(editor.buf().cursor(), (1, 1), "{:?}", dir);
+
+            let editor = run(dir.clone(), true);
+            assert_eq!(editor.buf().cursor(), (1, moved), "{:?}", dir);
+        }
+    }
+
     #[test]
     fn undo_modified() {
         let input = DummyInputs(vec![
@@ -649,6 +1423,21 @@ mod tests {
         input: [$($input:expr,)+],
         after: $after:expr,
         cursor: $cursor:expr,
+    }) => {
+        test_text_edit!($title, $title_undo, $title_redo {
+            lang: Language::Plain,
+            before: $before,
+            input: [$($input,)+],
+            after: $after,
+            cursor: $cursor,
+        });
+    };
+    ($title:ident, $title_undo:ident, $title_redo:ident {
+        lang: $lang:expr,
+        before: $before:expr,
+        input: [$($input:expr,)+],
+        after: $after:expr,
+        cursor: $cursor:expr,
     }) => {
         #[test]
         fn $title() {
@@ -660,6 +1449,7 @@ mod tests {
                 Discard,
                 Some((80, 24)),
             ).unwrap();
+            editor.set_lang($lang);
             editor.edit().unwrap();
 
             let actual = editor.lines().collect::<Vec<_>>();
@@ -692,6 +1482,7 @@ mod tests {
                 Discard,
                 Some((80, 24)),
             ).unwrap();
+            editor.set_lang($lang);
             editor.edit().unwrap();
 
             // After enough undo operations, buffer must be the same buffer as init
@@ -727,6 +1518,7 @@ mod tests {
                 Discard,
                 Some((80, 24)),
             ).unwrap();
+            editor.set_lang($lang);
             editor.edit().unwrap();
 
             // After enough undo and redo operations
@@ -1279,4 +2071,1793 @@ s",
             cursor: (0, 4),
         }
     );
+
+    test_text_edit!(
+        paste_text,
+        paste_text_undo,
+        paste_text_redo {
+            before: "
+abc
+def",
+            input: [
+                sp(RightKey),
+                paste("xy\tz"), // Paste in the middle of line
+                sp(DownKey),
+                sp(EndKey),
+                paste("12\n\n34\n"), // Paste multiple lines at end of line
+                ctrl('v'),
+                paste("あ\nい"), // Paste at end of buffer
+            ],
+            after: "
+axy\tzbc
+def12
+
+34
+
+あ
+い",
+            cursor: (1, 6),
+        }
+    );
+
+    test_text_edit!(
+        paste_text_in_middle_of_line,
+        paste_text_in_middle_of_line_undo,
+        paste_text_in_middle_of_line_redo {
+            before: "
+abcd",
+            input: [sp(RightKey), sp(RightKey), paste("x\ny\nz"), key('!'),],
+            after: "
+abx
+y
+z!cd",
+            cursor: (2, 2),
+        }
+    );
+
+    test_text_edit!(
+        insert_line_keep_indent,
+        insert_line_keep_indent_undo,
+        insert_line_keep_indent_redo {
+            before: "
+  ab
+\tcd",
+            input: [
+                sp(EndKey),
+                key('\r'), // Keep indentation
+                key('x'),
+                sp(DownKey),
+                sp(HomeKey),
+                sp(RightKey),
+                key('\r'), // Keep indentation and split line
+            ],
+            after: "
+  ab
+  x
+\t
+\tcd",
+            cursor: (1, 3),
+        }
+    );
+
+    test_text_edit!(
+        insert_line_smart_indent,
+        insert_line_smart_indent_undo,
+        insert_line_smart_indent_redo {
+            lang: Language::Rust,
+            before: "
+fn f() {",
+            input: [
+                sp(EndKey),
+                key('\r'), // Indent after {
+                key('g'),
+                key('('),
+                key('\r'), // Put closing bracket on next line
+                key('a'),
+                sp(DownKey),
+                sp(EndKey),
+                key(';'),
+                key('\r'),
+                key('}'), // Dedent by closing bracket
+            ],
+            after: "
+fn f() {
+    g(
+        a
+    );
+}",
+            cursor: (1, 4),
+        }
+    );
+
+    test_text_edit!(
+        closing_bracket_in_plain_text,
+        closing_bracket_in_plain_text_undo,
+        closing_bracket_in_plain_text_redo {
+            before: "
+    ",
+            input: [
+                sp(EndKey),
+                key(')'), // Not dedented since plain text has no block
+            ],
+            after: "
+    )",
+            cursor: (5, 0),
+        }
+    );
+
+    test_text_edit!(
+        closing_bracket_in_literal,
+        closing_bracket_in_literal_undo,
+        closing_bracket_in_literal_redo {
+            lang: Language::Rust,
+            before: "
+/*
+    ",
+            input: [
+                sp(DownKey),
+                sp(EndKey),
+                key('}'), // Not dedented in comment
+            ],
+            after: "
+/*
+    }",
+            cursor: (5, 1),
+        }
+    );
+
+    test_text_edit!(
+        insert_line_python_block,
+        insert_line_python_block_undo,
+        insert_line_python_block_redo {
+            lang: Language::Python,
+            before: "
+if x:",
+            input: [sp(EndKey), key('\r'), key('y'),],
+            after: "
+if x:
+    y",
+            cursor: (5, 1),
+        }
+    );
+
+    test_text_edit!(
+        reindent_region,
+        reindent_region_undo,
+        reindent_region_redo {
+            lang: Language::Rust,
+            before: "
+fn f() {
+let x = [
+1,
+ 2,
+];
+
+        g();
+}
+  h();",
+            input: [
+                sp(DownKey),
+                sp(RightKey),
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                alt('i'), // Reindent region
+                sp(DownKey),
+                alt('i'), // Reindent current line
+            ],
+            after: "
+fn f() {
+    let x = [
+        1,
+        2,
+    ];
+
+    g();
+}
+h();",
+            cursor: (0, 8),
+        }
+    );
+
+    test_text_edit!(
+        reindent_python_dedented_statement,
+        reindent_python_dedented_statement_undo,
+        reindent_python_dedented_statement_redo {
+            lang: Language::Python,
+            before: "
+if x:
+y
+z",
+            input: [
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                sp(EndKey),
+                alt('i'), // 'z' is not moved into the block
+            ],
+            after: "
+if x:
+    y
+z",
+            cursor: (1, 2),
+        }
+    );
+
+    test_text_edit!(
+        toggle_comment,
+        toggle_comment_undo,
+        toggle_comment_redo {
+            lang: Language::Rust,
+            before: "
+fn f() {
+    if x {
+        y();
+
+    }
+    // z();
+}",
+            input: [
+                sp(DownKey),
+                sp(EndKey),
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                alt(';'), // Comment out region
+                sp(DownKey),
+                alt(';'), // Uncomment current line
+                sp(UpKey),
+                sp(UpKey),
+                sp(UpKey),
+                alt(';'), // Uncomment one of commented lines
+            ],
+            after: "
+fn f() {
+    // if x {
+        y();
+
+    // }
+    z();
+}",
+            cursor: (0, 2),
+        }
+    );
+
+    test_text_edit!(
+        toggle_comment_python,
+        toggle_comment_python_undo,
+        toggle_comment_python_redo {
+            lang: Language::Python,
+            before: "
+x = 1
+  y = 2",
+            input: [sp(DownKey), sp(EndKey), alt(';'),],
+            after: "
+x = 1
+  # y = 2",
+            cursor: (9, 1),
+        }
+    );
+
+    test_text_edit!(
+        auto_pair_brackets,
+        auto_pair_brackets_undo,
+        auto_pair_brackets_redo {
+            lang: Language::Rust,
+            before: "
+",
+            input: [
+                key('f'),
+                key('('), // Insert pair
+                key('['),
+                key('1'),
+                key(']'), // Step over
+                key('x'),
+                key('\x08'),
+                key('{'),
+                key('\x08'), // Delete empty pair
+                key(')'),
+                key(';'),
+            ],
+            after: "
+f([1]);",
+            cursor: (7, 0),
+        }
+    );
+
+    test_text_edit!(
+        auto_pair_quotes,
+        auto_pair_quotes_undo,
+        auto_pair_quotes_redo {
+            lang: Language::JavaScript,
+            before: "
+",
+            input: [
+                key('\''), // Insert pair
+                key('('),  // Not paired in string literal
+                key('a'),
+                key('\''), // Step over
+                key(' '),
+                key('/'),
+                key('/'),
+                key(' '),
+                key('('), // Not paired in comment
+                key('"'),
+                key('d'),
+                key('o'),
+                key('n'),
+                key('\''), // Not paired after word character
+            ],
+            after: "
+'(a' // (\"don'",
+            cursor: (14, 0),
+        }
+    );
+
+    test_text_edit!(
+        no_delete_pair_in_literal,
+        no_delete_pair_in_literal_undo,
+        no_delete_pair_in_literal_redo {
+            lang: Language::Rust,
+            before: "
+s = \"()\";
+x = \"\";",
+            input: [
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                key('\x08'), // Only '(' is deleted in string literal
+                sp(DownKey),
+                key('\x08'), // Delete empty quotes
+            ],
+            after: "
+s = \")\";
+x = ;",
+            cursor: (4, 1),
+        }
+    );
+
+    test_text_edit!(
+        no_auto_pair_at_head_of_line_in_literal,
+        no_auto_pair_at_head_of_line_in_literal_undo,
+        no_auto_pair_at_head_of_line_in_literal_redo {
+            lang: Language::Rust,
+            before: "
+/* a
+*/
+// b
+ x",
+            input: [
+                sp(DownKey),
+                key('('), // In block comment
+                sp(DownKey),
+                sp(DownKey),
+                ctrl('a'),
+                key('('), // After line comment
+            ],
+            after: "
+/* a
+(*/
+// b
+() x",
+            cursor: (1, 3),
+        }
+    );
+
+    test_text_edit!(
+        no_auto_pair_in_plain_text,
+        no_auto_pair_in_plain_text_undo,
+        no_auto_pair_in_plain_text_redo {
+            before: "
+)",
+            input: [key('('), key(')'), key('\x08'),],
+            after: "
+()",
+            cursor: (1, 0),
+        }
+    );
+
+    #[test]
+    fn jump_to_matching_bracket_out_of_screen() {
+        let mut lines = vec!["fn f() {".to_string()];
+        lines.extend((0..100).map(|i| format!("    x{}();", i)));
+        lines.push("}".to_string());
+        for (input, cursor) in [
+            (vec![ctrl('e'), alt('m')], (0, 101)),
+            (vec![ctrl('e'), alt('m'), alt('m')], (7, 0)),
+        ] {
+            let input = DummyInputs(input);
+            let mut editor =
+                Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+            editor.set_lang(Language::Rust);
+            editor.edit().unwrap();
+            assert_eq!(editor.buf().cursor(), cursor);
+        }
+    }
+
+    test_text_edit!(
+        jump_to_matching_bracket,
+        jump_to_matching_bracket_undo,
+        jump_to_matching_bracket_redo {
+            lang: Language::Rust,
+            before: "
+fn main() {
+    let s = \"}\"; // }
+    if x {
+    }
+}",
+            input: [
+                ctrl('e'),
+                alt('m'), // Bracket before cursor
+                alt('m'), // Bracket at cursor
+                key('x'),
+            ],
+            after: "
+fn main() x{
+    let s = \"}\"; // }
+    if x {
+    }
+}",
+            cursor: (11, 0),
+        }
+    );
+
+    #[test]
+    fn highlight_matching_bracket() {
+        let input = DummyInputs(vec![ctrl('f'), ctrl('f')]);
+        let mut editor =
+            Editor::with_lines(["f(a[0])"].iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.set_lang(Language::Rust);
+        editor.edit().unwrap();
+
+        let matched = editor.hl.lines[0]
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| **h == Highlight::MatchBracket)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert_eq!(matched, vec![1, 6]);
+    }
+
+    test_text_edit!(
+        multi_cursors_at_next_word,
+        multi_cursors_at_next_word_undo,
+        multi_cursors_at_next_word_redo {
+            before: "
+foo bar
+foo foo_x
+  foo",
+            input: [
+                alt('j'),
+                alt('j'), // 'foo_x' is skipped
+                key('x'),
+                ctrl('e'),
+                key(';'),
+            ],
+            after: "
+xfoo bar;
+xfoo foo_x;
+  xfoo;",
+            cursor: (7, 2),
+        }
+    );
+
+    test_text_edit!(
+        multi_cursors_on_region_lines,
+        multi_cursors_on_region_lines_undo,
+        multi_cursors_on_region_lines_redo {
+            before: "
+ab
+cd
+ef",
+            input: [
+                ctrl(' '),
+                ctrl('n'),
+                ctrl('n'),
+                ctrl('f'),
+                alt('a'),
+                key('\r'),
+                key('-'),
+                ctrl('h'),
+                ctrl('h'), // Concat lines at each cursor
+                key('x'),
+            ],
+            after: "
+axb
+cxd
+exf",
+            cursor: (2, 2),
+        }
+    );
+
+    test_text_edit!(
+        remove_secondary_cursors,
+        remove_secondary_cursors_undo,
+        remove_secondary_cursors_redo {
+            before: "
+a
+a",
+            input: [alt('j'), alt('o'), key('b'),],
+            after: "
+a
+ba",
+            cursor: (1, 1),
+        }
+    );
+
+    test_text_edit!(
+        kill_and_yank_rectangle,
+        kill_and_yank_rectangle_undo,
+        kill_and_yank_rectangle_redo {
+            before: "
+ab\tcd
+0123456789
+xyz",
+            input: [
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl(' '),
+                ctrl('n'),
+                ctrl('e'),
+                alt('r'),
+                key('k'), // Columns are aligned by display width of tab
+                ctrl('n'),
+                alt('r'),
+                key('y'),
+            ],
+            after: "
+ab\t
+01234567
+xyzcd
+   89",
+            cursor: (5, 3),
+        }
+    );
+
+    test_text_edit!(
+        insert_and_replace_rectangle,
+        insert_and_replace_rectangle_undo,
+        insert_and_replace_rectangle_redo {
+            before: "
+foo bar
+foo baz",
+            input: [
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl('f'),
+                ctrl(' '),
+                ctrl('n'),
+                ctrl('e'),
+                alt('r'),
+                key('t'),
+                key('q'),
+                key('u'),
+                key('x'),
+                key('\r'),
+                ctrl('a'),
+                ctrl(' '),
+                ctrl('p'),
+                alt('r'),
+                key('i'),
+                key('/'),
+                key('/'),
+                key(' '),
+                key('\r'),
+            ],
+            after: "
+// foo qux
+// foo qux",
+            cursor: (3, 0),
+        }
+    );
+
+    test_text_edit!(
+        transpose_chars,
+        transpose_chars_undo,
+        transpose_chars_redo {
+            before: "
+abcd",
+            input: [ctrl('f'), ctrl('t'), ctrl('e'), ctrl('t'),],
+            after: "
+badc",
+            cursor: (4, 0),
+        }
+    );
+
+    test_text_edit!(
+        transpose_chars_with_combining_character,
+        transpose_chars_with_combining_character_undo,
+        transpose_chars_with_combining_character_redo {
+            before: "
+ae\u{301}o\u{308}",
+            input: [ctrl('f'), ctrl('t'), ctrl('e'), ctrl('t'),],
+            after: "
+e\u{301}o\u{308}a",
+            cursor: (5, 0),
+        }
+    );
+
+    test_text_edit!(
+        transpose_words,
+        transpose_words_undo,
+        transpose_words_redo {
+            before: "
+foo bar, baz",
+            input: [alt('t'), alt('t'),],
+            after: "
+bar baz, foo",
+            cursor: (12, 0),
+        }
+    );
+
+    test_text_edit!(
+        move_lines_up_and_down,
+        move_lines_up_and_down_undo,
+        move_lines_up_and_down_redo {
+            before: "
+a
+b
+c
+d",
+            input: [
+                InputSeq::alt(DownKey),
+                InputSeq::alt(DownKey),
+                ctrl(' '),
+                ctrl('p'),
+                InputSeq::alt(UpKey), // Move region
+                InputSeq::alt(UpKey), // Do nothing at top of buffer
+                key('x'),
+            ],
+            after: "
+xc
+a
+b
+d",
+            cursor: (1, 0),
+        }
+    );
+
+    test_text_edit!(
+        duplicate_lines,
+        duplicate_lines_undo,
+        duplicate_lines_redo {
+            before: "
+ab
+cd",
+            input: [ctrl('f'), alt('y'), ctrl(' '), ctrl('n'), alt('y'),],
+            after: "
+ab
+ab
+cd
+ab
+cd",
+            cursor: (1, 4),
+        }
+    );
+
+    test_text_edit!(
+        convert_case_of_word,
+        convert_case_of_word_undo,
+        convert_case_of_word_redo {
+            before: "
+straße foo_bar BAZ",
+            input: [alt('u'), alt('c'), alt('l'), alt('c'),],
+            after: "
+STRASSE Foo_bar baz",
+            cursor: (19, 0),
+        }
+    );
+
+    test_text_edit!(
+        convert_case_to_end_of_word,
+        convert_case_to_end_of_word_undo,
+        convert_case_to_end_of_word_redo {
+            before: "
+hello  world",
+            input: [
+                ctrl('f'),
+                ctrl('f'),
+                alt('u'), // From the cursor to the end of word
+                alt('c'), // Whitespaces before the next word are skipped
+            ],
+            after: "
+heLLO  World",
+            cursor: (12, 0),
+        }
+    );
+
+    test_text_edit!(
+        convert_case_of_region,
+        convert_case_of_region_undo,
+        convert_case_of_region_redo {
+            before: "
+hello world
+good bye",
+            input: [
+                ctrl('f'),
+                ctrl(' '),
+                ctrl('n'),
+                alt('c'),
+                alt('u'), // Word after cursor
+            ],
+            after: "
+hEllo World
+GOOD bye",
+            cursor: (4, 1),
+        }
+    );
+
+    test_text_edit!(
+        overwrite_mode,
+        overwrite_mode_undo,
+        overwrite_mode_redo {
+            before: "
+abc",
+            input: [
+                sp(InsertKey),
+                key('x'),
+                key('y'),
+                ctrl('e'),
+                key('z'), // Insert at end of line
+                sp(InsertKey),
+                ctrl('a'),
+                key('w'),
+            ],
+            after: "
+wxycz",
+            cursor: (1, 0),
+        }
+    );
+
+    test_text_edit!(
+        overwrite_combining_character,
+        overwrite_combining_character_undo,
+        overwrite_combining_character_redo {
+            before: "
+e\u{301}o\u{308}",
+            input: [
+                sp(InsertKey),
+                key('x'), // Accent is replaced together
+                key('y'),
+            ],
+            after: "
+xy",
+            cursor: (2, 0),
+        }
+    );
+
+    test_text_edit!(
+        replay_keyboard_macro,
+        replay_keyboard_macro_undo,
+        replay_keyboard_macro_redo {
+            before: "
+a
+b
+c",
+            input: [
+                alt('('),
+                ctrl('a'),
+                key('-'),
+                ctrl('n'),
+                alt(')'),
+                alt('e'),
+                alt('E'),
+                key('1'),
+                key('\r'),
+            ],
+            after: "
+-a
+-b
+-c",
+            cursor: (0, 3),
+        }
+    );
+
+    test_text_edit!(
+        replay_keyboard_macro_until_search_fails,
+        replay_keyboard_macro_until_search_fails_undo,
+        replay_keyboard_macro_until_search_fails_redo {
+            before: "
+a1 a2
+a3",
+            input: [
+                alt('('),
+                ctrl('g'),
+                key('a'),
+                key('\r'),
+                ctrl('d'),
+                key('X'),
+                alt(')'),
+                alt('E'),
+                key('\r'), // Repeat until search fails
+            ],
+            after: "
+X1 X2
+X3",
+            cursor: (1, 1),
+        }
+    );
+
+    #[test]
+    fn save_and_load_keyboard_macro() {
+        let path = env::temp_dir().join(format!("kiro-test-macro-{}", process::id()));
+        let path_keys: Vec<_> = path.to_str().unwrap().chars().map(utf8).collect();
+
+        let mut input = vec![
+            alt('('),
+            key('x'),
+            sp(Paste("y\nz".to_string())),
+            alt(')'),
+            alt('S'),
+        ];
+        input.extend(path_keys.iter().cloned());
+        input.push(key('\r'));
+        input.push(alt('L'));
+        input.extend(path_keys);
+        input.push(key('\r'));
+        input.push(alt('e'));
+
+        let mut editor =
+            Editor::with_lines([""].iter(), DummyInputs(input), Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved, "0x78\nPASTE:y\\nz\n");
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["xy", "zxy", "z"]);
+    }
+
+    test_text_edit!(
+        repeat_insert_char,
+        repeat_insert_char_undo,
+        repeat_insert_char_redo {
+            before: "
+ab",
+            input: [ctrl('f'), alt('1'), alt('2'), key('-'), key('x'),],
+            after: "
+a------------xb",
+            cursor: (14, 0),
+        }
+    );
+
+    test_text_edit!(
+        repeat_delete_word,
+        repeat_delete_word_undo,
+        repeat_delete_word_redo {
+            before: "
+a b c d e f
+g",
+            input: [ctrl('e'), alt('3'), ctrl('w'), alt('2'), sp(DownKey),],
+            after: "
+a b c 
+g",
+            cursor: (0, 2),
+        }
+    );
+
+    test_text_edit!(
+        repeat_count_survives_idle_ticks,
+        repeat_count_survives_idle_ticks_undo,
+        repeat_count_survives_idle_ticks_redo {
+            before: "
+abc",
+            input: [alt('3'), sp(Unidentified), sp(Unidentified), key('x'),],
+            after: "
+xxxabc",
+            cursor: (3, 0),
+        }
+    );
+
+    #[test]
+    fn repeat_count_is_limited() {
+        let input = DummyInputs(vec![alt('9'); 20]);
+        let mut editor =
+            Editor::with_lines(["abc"].iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.screen().message_text(),
+            format!("Repeat next command {} times", MAX_REPEAT_COUNT)
+        );
+
+        let mut keys = vec![alt('9'); 20];
+        keys.push(ctrl('f'));
+        let input = DummyInputs(keys);
+        let mut editor =
+            Editor::with_lines(["abc"].iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.buf().cursor(), (0, 1));
+    }
+
+    test_text_edit!(
+        repeat_count_zero,
+        repeat_count_zero_undo,
+        repeat_count_zero_redo {
+            before: "
+abc",
+            input: [
+                alt('0'),
+                key('x'), // Count 0 is ignored
+                alt('0'),
+                alt('2'),
+                key('y'),
+            ],
+            after: "
+xyyabc",
+            cursor: (3, 0),
+        }
+    );
+
+    test_text_edit!(
+        repeat_count_does_not_repeat_prompt,
+        repeat_count_does_not_repeat_prompt_undo,
+        repeat_count_does_not_repeat_prompt_redo {
+            before: "
+abc
+def",
+            input: [
+                alt('3'),
+                alt('g'),
+                key('2'),
+                key('\r'),
+                key('x'), // Not consumed by second prompt
+            ],
+            after: "
+abc
+xdef",
+            cursor: (1, 1),
+        }
+    );
+
+    #[test]
+    fn repeated_command_is_one_undo_entry() {
+        let input = DummyInputs(vec![ctrl('e'), key('x'), alt('3'), ctrl('w'), ctrl('u')]);
+        let mut editor =
+            Editor::with_lines(["a b c d e f"].iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["a b c d e fx"]);
+    }
+
+    test_text_edit!(
+        go_to_line,
+        go_to_line_undo,
+        go_to_line_redo {
+            before: "
+1
+2
+3
+4
+5
+6
+7
+8
+9
+10",
+            input: [
+                alt('g'),
+                key('3'),
+                key('\r'),
+                key('a'),
+                alt('g'),
+                key('+'),
+                key('2'),
+                key('\r'),
+                key('b'),
+                alt('g'),
+                key('1'),
+                key('0'),
+                key('0'),
+                key('%'),
+                key('\r'),
+                key('c'),
+                alt('g'),
+                key('2'),
+                key(':'),
+                key('2'),
+                key('\r'),
+                key('d'),
+            ],
+            after: "
+1
+2d
+a3
+4
+b5
+6
+7
+8
+9
+c10",
+            cursor: (2, 1),
+        }
+    );
+
+    #[test]
+    fn go_to_line_out_of_range_percentage() {
+        for percent in ["200%", "18446744073709551615%"] {
+            let mut keys = prompt_keys(alt('g'), percent);
+            keys.push(key('x'));
+            let input = DummyInputs(keys);
+            let lines = ["a", "b", "c"];
+            let mut editor =
+                Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+            editor.edit().unwrap();
+            assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["a", "b", "xc"]);
+        }
+    }
+
+    test_text_edit!(
+        fill_paragraph,
+        fill_paragraph_undo,
+        fill_paragraph_redo {
+            before: "
+aaa bbb ccc
+ddd
+
+eee",
+            input: [alt('q'),],
+            after: "
+aaa bbb ccc ddd
+
+eee",
+            cursor: (0, 0),
+        }
+    );
+
+    test_text_edit!(
+        fill_comment_paragraph,
+        fill_comment_paragraph_undo,
+        fill_comment_paragraph_redo {
+            lang: Language::Rust,
+            before: "
+fn f() {
+    // aaaa bbbb cccc dddd eeee ffff gggg hhhh iiii jjjj kkkk llll mmmm nnnn oooo pppp qqqq
+    // rrrr
+    //
+    // ssss
+}",
+            input: [sp(DownKey), alt('q'),],
+            after: "
+fn f() {
+    // aaaa bbbb cccc dddd eeee ffff gggg hhhh iiii jjjj kkkk llll mmmm nnnn
+    // oooo pppp qqqq rrrr
+    //
+    // ssss
+}",
+            cursor: (7, 1),
+        }
+    );
+
+    test_text_edit!(
+        fill_block_comment_wide_chars,
+        fill_block_comment_wide_chars_undo,
+        fill_block_comment_wide_chars_redo {
+            lang: Language::C,
+            before: "
+/*
+ * あいうえお かきくけこ さしすせそ たちつてと なにぬねの はひふへほ まみむめも やゆよ
+ */",
+            input: [sp(DownKey), alt('q'),],
+            after: "
+/*
+ * あいうえお かきくけこ さしすせそ たちつてと なにぬねの はひふへほ まみむめも
+ * やゆよ
+ */",
+            cursor: (3, 1),
+        }
+    );
+
+    test_text_edit!(
+        unfill_paragraph,
+        unfill_paragraph_undo,
+        unfill_paragraph_redo {
+            lang: Language::Python,
+            before: "
+  # aaa
+  # bbb   ccc
+  # ddd
+x = 1",
+            input: [sp(DownKey), sp(EndKey), alt('Q'),],
+            after: "
+  # aaa bbb ccc ddd
+x = 1",
+            cursor: (15, 0),
+        }
+    );
+
+    test_text_edit!(
+        sort_lines_in_region,
+        sort_lines_in_region_undo,
+        sort_lines_in_region_redo {
+            before: "
+header
+banana
+Cherry
+apple
+footer",
+            input: [
+                sp(DownKey),
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                alt('s'),
+                key('s'),
+            ],
+            after: "
+header
+Cherry
+apple
+banana
+footer",
+            cursor: (0, 1),
+        }
+    );
+
+    test_text_edit!(
+        sort_lines_ignore_case_reverse,
+        sort_lines_ignore_case_reverse_undo,
+        sort_lines_ignore_case_reverse_redo {
+            before: "
+banana
+Cherry
+apple",
+            input: [ctrl(' '), sp(DownKey), sp(DownKey), alt('s'), key('I'),],
+            after: "
+Cherry
+banana
+apple",
+            cursor: (0, 0),
+        }
+    );
+
+    test_text_edit!(
+        sort_lines_numerically,
+        sort_lines_numerically_undo,
+        sort_lines_numerically_redo {
+            before: "
+10 ten
+-1 minus one
+2.5 two and half
+none",
+            input: [
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                alt('s'),
+                key('n'),
+            ],
+            after: "
+-1 minus one
+none
+2.5 two and half
+10 ten",
+            cursor: (0, 0),
+        }
+    );
+
+    test_text_edit!(
+        uniq_lines_in_region,
+        uniq_lines_in_region_undo,
+        uniq_lines_in_region_redo {
+            before: "
+a
+a
+b
+a
+a",
+            input: [
+                ctrl(' '),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                sp(DownKey),
+                alt('s'),
+                key('u'),
+            ],
+            after: "
+a
+b
+a",
+            cursor: (0, 0),
+        }
+    );
+
+    test_text_edit!(
+        reverse_lines_in_region,
+        reverse_lines_in_region_undo,
+        reverse_lines_in_region_redo {
+            before: "
+a
+b
+c",
+            input: [
+                sp(DownKey),
+                sp(DownKey),
+                ctrl(' '),
+                sp(UpKey),
+                sp(UpKey),
+                alt('s'),
+                key('r'),
+            ],
+            after: "
+c
+b
+a",
+            cursor: (0, 0),
+        }
+    );
+
+    #[test]
+    fn sort_lines_without_mark() {
+        let input = DummyInputs(vec![alt('s'), key('s')]);
+        let lines = ["b", "a"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["b", "a"]);
+        assert_eq!(editor.screen().message_text(), "Mark is not set");
+    }
+
+    fn prompt_keys(prefix: InputSeq, text: &str) -> Vec<InputSeq> {
+        let mut keys = vec![prefix];
+        keys.extend(text.chars().map(key));
+        keys.push(key('\r'));
+        keys
+    }
+
+    #[test]
+    fn filter_buffer_through_command() {
+        let input = DummyInputs(prompt_keys(alt('|'), "sort -r"));
+        let lines = ["b", "c", "a"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn filter_region_through_command() {
+        let mut keys = vec![sp(DownKey), ctrl(' '), sp(DownKey)];
+        keys.extend(prompt_keys(alt('|'), "tr a-z A-Z; echo added"));
+        keys.push(ctrl('u'));
+        let input = DummyInputs(keys);
+        let lines = ["aaa", "bbb", "ccc", "ddd"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        // Filtering is undone in one step
+        assert_eq!(editor.lines().collect::<Vec<_>>(), lines);
+
+        let mut keys = vec![sp(DownKey), ctrl(' '), sp(DownKey)];
+        keys.extend(prompt_keys(alt('|'), "tr a-z A-Z; echo added"));
+        let input = DummyInputs(keys);
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.lines().collect::<Vec<_>>(),
+            vec!["aaa", "BBB", "CCC", "added", "ddd"],
+        );
+        assert_eq!(editor.buf().cursor(), (0, 1));
+    }
+
+    #[test]
+    fn filter_through_failing_command() {
+        let input = DummyInputs(prompt_keys(alt('|'), "echo oops >&2; exit 3"));
+        let lines = ["aaa", "bbb"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["aaa", "bbb"]);
+        assert_eq!(
+            editor.screen().message_text(),
+            "'echo oops >&2; exit 3' failed with exit status 3: oops",
+        );
+    }
+
+    #[test]
+    fn cancel_filtering_through_command() {
+        let mut keys = prompt_keys(alt('|'), "sleep 10; echo foo");
+        keys.push(ctrl('g'));
+        keys.push(key('x'));
+        let input = DummyInputs(keys);
+        let lines = ["aaa", "bbb"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["xaaa", "bbb"]);
+        assert_eq!(
+            editor.screen().message_text(),
+            "Canceled running 'sleep 10; echo foo'"
+        );
+    }
+
+    #[test]
+    fn insert_command_output() {
+        let mut keys = vec![key('a'), key('b'), sp(LeftKey)];
+        keys.extend(prompt_keys(alt('!'), "echo foo; echo bar"));
+        keys.push(ctrl('u'));
+        keys.push(ctrl('r'));
+        let input = DummyInputs(keys);
+        let mut editor = Editor::new(input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["afoo", "barb"]);
+        assert_eq!(editor.buf().cursor(), (3, 1));
+    }
+
+    #[test]
+    fn insert_output_of_failing_command() {
+        let input = DummyInputs(prompt_keys(alt('!'), "echo foo; exit 1"));
+        let mut editor = Editor::new(input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec![""]);
+        assert_eq!(
+            editor.screen().message_text(),
+            "'echo foo; exit 1' failed with exit status 1",
+        );
+    }
+
+    #[test]
+    fn cancel_running_command() {
+        let mut keys = prompt_keys(alt('!'), "sleep 10; echo foo");
+        keys.push(ctrl('g'));
+        keys.push(key('x'));
+        let input = DummyInputs(keys);
+        let mut editor = Editor::new(input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["x"]);
+    }
+
+    test_text_edit!(
+        delete_grapheme_clusters,
+        delete_grapheme_clusters_undo,
+        delete_grapheme_clusters_redo {
+            before: "
+ae\u{301}\u{1F1EF}\u{1F1F5}b",
+            input: [sp(EndKey), sp(LeftKey), ctrl('h'), ctrl('h'),],
+            after: "
+ab",
+            cursor: (1, 0),
+        }
+    );
+
+    test_text_edit!(
+        move_over_grapheme_clusters,
+        move_over_grapheme_clusters_undo,
+        move_over_grapheme_clusters_redo {
+            before: "
+e\u{301}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}
+abcdef",
+            input: [
+                ctrl('f'),
+                key('1'),
+                ctrl('f'),
+                key('2'),
+                sp(DownKey),
+                sp(EndKey),
+                sp(UpKey),
+                ctrl('d'),
+            ],
+            after: "
+e\u{301}12
+abcdef",
+            cursor: (3, 0),
+        }
+    );
+
+    #[test]
+    fn width_of_grapheme_clusters() {
+        use crate::row::Row;
+
+        let row =
+            Row::new("a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}e\u{301}\u{1F1EF}\u{1F1F5}\tb")
+                .unwrap();
+        assert_eq!(row.rx_from_cx(1), 1);
+        assert_eq!(row.rx_from_cx(6), 3);
+        assert_eq!(row.rx_from_cx(8), 4);
+        assert_eq!(row.rx_from_cx(10), 6);
+        assert_eq!(row.rx_from_cx(11), 8);
+        assert_eq!(row.prev_boundary(6), 1);
+        assert_eq!(row.next_boundary(1), 6);
+        assert_eq!(row.next_boundary(6), 8);
+        assert!(!row.is_boundary(3));
+        assert_eq!(row.cx_from_rx(2), 6);
+        assert_eq!(row.render_widths().sum::<usize>(), 9);
+    }
+
+    #[test]
+    fn move_cursor_by_unicode_word() {
+        let line = "漢字とカタカナ\u{3000}café_été.x";
+        for (input, cursor) in [
+            (vec![alt('f')], (2, 0)),                     // Between Han and Hiragana
+            (vec![alt('f'), alt('f')], (3, 0)),           // Between Hiragana and Katakana
+            (vec![alt('f'), alt('f'), alt('f')], (8, 0)), // After ideographic space
+            (vec![sp(EndKey), alt('b')], (17, 0)),
+            (vec![sp(EndKey), alt('b'), alt('b')], (16, 0)),
+            (vec![sp(EndKey), alt('b'), alt('b'), alt('b')], (8, 0)), // Start of accented identifier
+        ] {
+            let input = DummyInputs(input);
+            let lines = [line];
+            let mut editor =
+                Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+            editor.edit().unwrap();
+            assert_eq!(editor.buf().cursor(), cursor);
+        }
+    }
+
+    #[test]
+    fn move_cursor_by_word_with_combining_marks() {
+        // 'été' with combining acute accents (U+0301) and Hindi word including vowel signs
+        let line = "e\u{301}te\u{301}.x नमस्ते दुनिया";
+        for (input, cursor) in [
+            (vec![alt('f')], (5, 0)),
+            (vec![alt('f'), alt('f')], (6, 0)),
+            (vec![alt('f'), alt('f'), alt('f')], (8, 0)),
+            (vec![alt('f'), alt('f'), alt('f'), alt('f')], (15, 0)),
+            (vec![sp(EndKey), alt('b')], (15, 0)),
+            (vec![sp(EndKey), alt('b'), alt('b')], (8, 0)),
+            (vec![alt('f'), alt('b')], (0, 0)),
+        ] {
+            let input = DummyInputs(input);
+            let lines = [line];
+            let mut editor =
+                Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+            editor.edit().unwrap();
+            assert_eq!(editor.buf().cursor(), cursor);
+        }
+    }
+
+    test_text_edit!(
+        delete_word_with_combining_marks,
+        delete_word_with_combining_marks_undo,
+        delete_word_with_combining_marks_redo {
+            before: "
+e\u{301}te\u{301} नमस्ते दुनिया",
+            input: [
+                alt('d'), // Delete 'été'
+                sp(EndKey),
+                ctrl('w'), // Delete 'दुनिया'
+            ],
+            after: "
+ नमस्ते ",
+            cursor: (8, 0),
+        }
+    );
+
+    test_text_edit!(
+        delete_unicode_word,
+        delete_unicode_word_undo,
+        delete_unicode_word_redo {
+            before: "
+foo.bär 日本語です",
+            input: [
+                sp(EndKey),
+                ctrl('w'), // Delete 'です'
+                ctrl('w'), // Delete '日本語'
+                ctrl('w'), // Delete 'bär '
+            ],
+            after: "
+foo.",
+            cursor: (4, 0),
+        }
+    );
+
+    test_text_edit!(
+        language_word_chars,
+        language_word_chars_undo,
+        language_word_chars_redo {
+            lang: Language::JavaScript,
+            before: "
+$foo.$bar",
+            input: [alt('f'), key('x'), sp(EndKey), ctrl('w'),],
+            after: "
+$foox.",
+            cursor: (6, 0),
+        }
+    );
+
+    test_text_edit!(
+        delete_word_forward,
+        delete_word_forward_undo,
+        delete_word_forward_redo {
+            before: "
+foo.bar  baz
+qux",
+            input: [
+                alt('d'), // Delete 'foo'
+                alt('d'), // Delete '.'
+                alt('d'), // Delete 'bar'
+                alt('d'), // Delete '  baz'
+                alt('d'), // Join next line
+                alt('d'), // Delete 'qux'
+                alt('d'), // Do nothing at end of buffer
+            ],
+            after: "
+
+",
+            cursor: (0, 0),
+        }
+    );
+
+    test_text_edit!(
+        delete_word_backward,
+        delete_word_backward_undo,
+        delete_word_backward_redo {
+            before: "
+foo bar
+baz",
+            input: [
+                sp(DownKey),
+                alt('\x7f'), // Join previous line at head of line
+                alt('\x7f'), // Delete 'bar'
+            ],
+            after: "
+foo baz",
+            cursor: (4, 0),
+        }
+    );
+
+    test_text_edit!(
+        delete_whole_line,
+        delete_whole_line_undo,
+        delete_whole_line_redo {
+            before: "
+abc
+def
+ghi
+jkl",
+            input: [
+                sp(DownKey),
+                sp(RightKey),
+                alt('k'), // Delete 'def' in the middle
+                key('X'), // Typed at the head of the next line
+                sp(UpKey),
+                alt('k'), // Delete 'abc'
+                sp(DownKey),
+                alt('k'), // Delete 'jkl' at the last line
+                alt('k'), // Delete 'Xghi' as the only line
+                key('Y'),
+            ],
+            after: "
+Y",
+            cursor: (1, 0),
+        }
+    );
+
+    test_text_edit!(
+        zap_to_char,
+        zap_to_char_undo,
+        zap_to_char_redo {
+            before: "
+abc,def
+ghi,jkl",
+            input: [
+                sp(RightKey),
+                alt('z'),
+                key(','), // Delete 'bc,'
+                alt('z'),
+                key(','), // Delete 'def\nghi,' over lines
+                alt('z'),
+                key('x'), // Not found
+                alt('z'),
+                ctrl('g'), // Cancel
+            ],
+            after: "
+ajkl",
+            cursor: (1, 0),
+        }
+    );
+
+    #[test]
+    fn set_cursor_position_before_edit() {
+        let input = DummyInputs(vec![key('x')]);
+        let lines = ["abc", "def", "ghi"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.set_cursor_position(0, 2, 3);
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.lines().collect::<Vec<_>>(),
+            vec!["abc", "dexf", "ghi"]
+        );
+
+        let input = DummyInputs(vec![key('x')]);
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.set_cursor_position(0, 100, 100); // Clamped
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.lines().collect::<Vec<_>>(),
+            vec!["abc", "def", "ghix"]
+        );
+    }
+
+    #[test]
+    fn set_cursor_position_of_multiple_buffers() {
+        let dir = env::temp_dir().join(format!("kiro-test-cursor-position-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = (1..=100)
+            .map(|i| format!("line{}\n", i))
+            .collect::<String>();
+        let paths = [dir.join("a.txt"), dir.join("b.txt")];
+        for path in paths.iter() {
+            fs::write(path, &text).unwrap();
+        }
+
+        let input = DummyInputs(vec![key('x'), ctrl('x'), key('y')]);
+        let mut editor = Editor::open(input, Discard, Some((80, 24)), &paths).unwrap();
+        editor.set_cursor_position(0, 50, 1);
+        editor.set_cursor_position(1, 80, 2);
+        // Setting position of other buffer does not affect scroll of current buffer
+        let center = editor.screen.rows() / 2;
+        assert_eq!(editor.screen.rowoff, 49 - center);
+        editor.edit().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(editor.bufs[0].rows()[49].buffer(), "xline50");
+        assert_eq!(editor.bufs[1].rows()[79].buffer(), "lyine80");
+        assert_eq!(editor.screen.rowoff, 79 - center);
+    }
+
+    #[test]
+    fn soft_wrap_move_cursor_by_screen_line() {
+        let input = DummyInputs(vec![
+            alt('w'),
+            ctrl('f'),
+            ctrl('f'),
+            sp(DownKey),
+            key('1'), // Second screen line of first row
+            sp(DownKey),
+            key('2'), // Clamped to end of short row
+            sp(UpKey),
+            key('3'),
+            sp(UpKey),
+            key('4'),
+        ]);
+        let lines = ["aaaa bbbb cccc", "x"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((10, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.lines().collect::<Vec<_>>(),
+            vec!["aaa4a bbbb cc31cc", "x2"]
+        );
+        assert_eq!(editor.buf().cursor(), (4, 0));
+    }
+
+    #[test]
+    fn soft_wrap_scroll_by_screen_line() {
+        let mut keys = vec![alt('w')];
+        keys.extend(std::iter::repeat_n(sp(DownKey), 5));
+        let input = DummyInputs(keys);
+        let lines = ["aaaa bbbb cccc"; 5];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((10, 6))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.buf().cursor(), (10, 2));
+        assert_eq!(editor.screen().rowoff, 1);
+    }
+
+    #[test]
+    fn indent_config_per_language() {
+        let mut config = IndentConfig::default();
+        config.add("2").unwrap();
+        config.add("c=tab:4").unwrap();
+        assert!(config.add("foo=2").is_err());
+        assert!(config.add("tab:0").is_err());
+
+        let input = DummyInputs(vec![ctrl('i'), key('a')]);
+        let mut editor = Editor::new(input, Discard, Some((80, 24))).unwrap();
+        editor.set_lang(Language::C);
+        editor.set_indent_config(config);
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["\ta"]);
+        assert_eq!(editor.buf().rows()[0].rx_from_cx(1), 4);
+    }
+
+    #[test]
+    fn set_indent_of_buffer() {
+        let mut keys = prompt_keys(alt('I'), "3:2");
+        keys.extend([ctrl('i'), key('a')]);
+        let input = DummyInputs(keys);
+        let lines = ["\tx"];
+        let mut editor = Editor::with_lines(lines.iter(), input, Discard, Some((80, 24))).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["   a\tx"]);
+        assert_eq!(editor.buf().rows()[0].rx_from_cx(5), 6);
+        assert_eq!(
+            editor.screen().message_text(),
+            "Indent: 3 spaces, tab stop: 2"
+        );
+    }
+
+    #[test]
+    fn apply_editorconfig() {
+        let dir = env::temp_dir().join(format!("kiro-test-editorconfig-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let config = "root = true
+
+[*]
+indent_style = space
+indent_size = 2
+
+# Comment
+[*.{c,h}]
+indent_style = tab
+tab_width = 4
+end_of_line = crlf
+trim_trailing_whitespace = true
+insert_final_newline = false
+
+[sub/**.txt]
+charset = latin1
+";
+        fs::write(dir.join(".editorconfig"), config).unwrap();
+        let c_file = dir.join("test.c");
+        fs::write(&c_file, "a  \r\nb\r\n").unwrap();
+        let txt_file = dir.join("sub").join("test.txt");
+        fs::write(&txt_file, b"caf\xe9\n").unwrap();
+
+        let input = DummyInputs(vec![ctrl('i'), ctrl('s'), ctrl('x'), ctrl('i'), ctrl('s')]);
+        let paths = [&c_file, &txt_file];
+        let mut editor = Editor::open(input, Discard, Some((80, 24)), &paths).unwrap();
+        editor.edit().unwrap();
+        let c_saved = fs::read(&c_file).unwrap();
+        let txt_saved = fs::read(&txt_file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(c_saved, b"\ta\r\nb");
+        assert_eq!(txt_saved, b"  caf\xe9\n");
+        assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["  caf\u{e9}"]);
+    }
+
+    #[test]
+    fn apply_editorconfig_on_save_as() {
+        let dir = env::temp_dir().join(format!("kiro-test-save-as-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = "[*.c]\nend_of_line = crlf\ntab_width = 4\n";
+        fs::write(dir.join(".editorconfig"), config).unwrap();
+        let c_file = dir.join("new.c");
+
+        let mut keys = prompt_keys(ctrl('s'), c_file.to_str().unwrap());
+        keys.extend([ctrl('i'), key('a'), ctrl('m'), key('b'), ctrl('s')]);
+        let input = DummyInputs(keys);
+        let mut config = IndentConfig::default();
+        config.add("c=tab").unwrap();
+        let mut editor = Editor::new(input, Discard, Some((80, 24))).unwrap();
+        editor.set_indent_config(config);
+        editor.edit().unwrap();
+        let saved = fs::read(&c_file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, b"\ta\r\n\tb\r\n");
+        assert_eq!(editor.buf().rows()[0].rx_from_cx(1), 4);
+    }
+
+    #[test]
+    fn detect_indent_from_file() {
+        let dir = env::temp_dir().join(format!("kiro-test-detect-indent-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rs_file = dir.join("test.rs");
+        fs::write(
+            &rs_file,
+            "fn f() {\n  if x {\n    y();\n  }\n}\n/*\n * z\n */\n",
+        )
+        .unwrap();
+        let py_file = dir.join("test.py");
+        fs::write(&py_file, "def f():\n\tif x:\n\t\ty()\n").unwrap();
+
+        let input = DummyInputs(vec![ctrl('i'), ctrl('x'), ctrl('i')]);
+        let paths = [&rs_file, &py_file];
+        let mut editor = Editor::open(input, Discard, Some((80, 24)), &paths).unwrap();
+        editor.edit().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(editor.lines().next().unwrap(), "\tdef f():");
+        assert!(editor.status_bar.right().contains("tab:8"));
+        editor.next_buffer();
+        assert_eq!(editor.lines().next().unwrap(), "  fn f() {");
+        editor.refresh_status_bar();
+        assert!(editor.status_bar.right().contains("spaces:2"));
+    }
 }
@@ -0,0 +1,577 @@
+// EditorConfig support: https://editorconfig.org/
+//
+// `.editorconfig` files are searched from the directory of a file up to the root directory (or the
+// directory whose file has `root = true`). Properties in files closer to the edited file and in
+// later sections take precedence.
+use crate::language::{Indent, IndentSetting, MAX_INDENT_WIDTH};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Width of indentation by spaces when only `indent_style = space` is given for language which
+// indents with tab by default
+const DEFAULT_INDENT_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Charset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Utf8Bom => "utf-8-bom",
+            Charset::Latin1 => "latin1",
+            Charset::Utf16Be => "utf-16be",
+            Charset::Utf16Le => "utf-16le",
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+            if !bytes.len().is_multiple_of(2) {
+                return None;
+            }
+            let units: Vec<_> = bytes.chunks(2).map(|c| unit([c[0], c[1]])).collect();
+            let units = units.strip_prefix(&[0xfeff]).unwrap_or(&units);
+            String::from_utf16(units).ok()
+        }
+
+        match self {
+            Charset::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Charset::Utf8Bom => {
+                let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).ok()
+            }
+            Charset::Latin1 => Some(bytes.iter().map(|&b| b as char).collect()),
+            Charset::Utf16Be => utf16(bytes, u16::from_be_bytes),
+            Charset::Utf16Le => utf16(bytes, u16::from_le_bytes),
+        }
+    }
+
+    // Returns the character which cannot be encoded on error
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Charset::Utf8 => Ok(text.as_bytes().to_vec()),
+            Charset::Utf8Bom => {
+                let mut bytes = b"\xef\xbb\xbf".to_vec();
+                bytes.extend_from_slice(text.as_bytes());
+                Ok(bytes)
+            }
+            Charset::Latin1 => text
+                .chars()
+                .map(|c| {
+                    if (c as u32) < 0x100 {
+                        Ok(c as u8)
+                    } else {
+                        Err(c)
+                    }
+                })
+                .collect(),
+            Charset::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Charset::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+        }
+    }
+}
+
+// How text buffer is written to file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub charset: Charset,
+    pub trim_trailing_whitespace: bool,
+    pub final_newline: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            line_ending: LineEnding::Lf,
+            charset: Charset::Utf8,
+            trim_trailing_whitespace: false,
+            final_newline: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IndentSize {
+    Tab,
+    Width(usize),
+}
+
+// Properties of EditorConfig which apply to a file. None means that the property is not set
+#[derive(Debug, Default, PartialEq)]
+pub struct EditorConfig {
+    indent_tab: Option<bool>,
+    indent_size: Option<IndentSize>,
+    tab_width: Option<usize>,
+    end_of_line: Option<LineEnding>,
+    charset: Option<Charset>,
+    trim_trailing_whitespace: Option<bool>,
+    insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    // Load properties for the file from `.editorconfig` files. Files which cannot be read are
+    // ignored since they are not essential for editing
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            match env::current_dir() {
+                Ok(dir) => dir.join(path),
+                Err(_) => return Self::default(),
+            }
+        };
+
+        let mut configs = vec![];
+        for dir in path.ancestors().skip(1) {
+            if let Ok(content) = fs::read_to_string(dir.join(".editorconfig")) {
+                let root = is_root(&content);
+                configs.push((dir.to_path_buf(), content));
+                if root {
+                    break;
+                }
+            }
+        }
+
+        let mut props = HashMap::new();
+        for (dir, content) in configs.iter().rev() {
+            apply_sections(content, &relative_path(&path, dir), &mut props);
+        }
+        Self::from_props(&props)
+    }
+
+    fn from_props(props: &HashMap<String, String>) -> Self {
+        let get = |key: &str| props.get(key).map(String::as_str);
+        let width = |value: &str| {
+            value
+                .parse()
+                .ok()
+                .filter(|w| (1..=MAX_INDENT_WIDTH).contains(w))
+        };
+        let boolean = |value| match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+
+        Self {
+            indent_tab: match get("indent_style") {
+                Some("tab") => Some(true),
+                Some("space") => Some(false),
+                _ => None,
+            },
+            indent_size: match get("indent_size") {
+                Some("tab") => Some(IndentSize::Tab),
+                Some(size) => width(size).map(IndentSize::Width),
+                None => None,
+            },
+            tab_width: get("tab_width").and_then(width),
+            end_of_line: match get("end_of_line") {
+                Some("lf") => Some(LineEnding::Lf),
+                Some("crlf") => Some(LineEnding::CrLf),
+                Some("cr") => Some(LineEnding::Cr),
+                _ => None,
+            },
+            charset: match get("charset") {
+                Some("utf-8") => Some(Charset::Utf8),
+                Some("utf-8-bom") => Some(Charset::Utf8Bom),
+                Some("latin1") => Some(Charset::Latin1),
+                Some("utf-16be") => Some(Charset::Utf16Be),
+                Some("utf-16le") => Some(Charset::Utf16Le),
+                _ => None,
+            },
+            trim_trailing_whitespace: get("trim_trailing_whitespace").and_then(boolean),
+            insert_final_newline: get("insert_final_newline").and_then(boolean),
+        }
+    }
+
+    pub fn file_format(&self) -> FileFormat {
+        let default = FileFormat::default();
+        FileFormat {
+            line_ending: self.end_of_line.unwrap_or(default.line_ending),
+            charset: self.charset.unwrap_or(default.charset),
+            trim_trailing_whitespace: self
+                .trim_trailing_whitespace
+                .unwrap_or(default.trim_trailing_whitespace),
+            final_newline: self.insert_final_newline.unwrap_or(default.final_newline),
+        }
+    }
+
+    // Indentation and tab stop set by `indent_style`, `indent_size` and `tab_width`. `default` is
+    // indentation of the language used when only some of them are set
+    pub fn indent_setting(&self, default: Indent) -> IndentSetting {
+        let tab_stop = match (self.tab_width, self.indent_size) {
+            (Some(w), _) | (None, Some(IndentSize::Width(w))) => Some(w),
+            _ => None,
+        };
+        let width = match self.indent_size {
+            Some(IndentSize::Width(w)) => Some(w),
+            Some(IndentSize::Tab) => tab_stop,
+            None => None,
+        };
+        let indent = match (self.indent_tab, default) {
+            (Some(true), _) => Some(Indent::AsIs),
+            (Some(false), Indent::Spaces(w)) => Some(Indent::Spaces(width.unwrap_or(w))),
+            (Some(false), Indent::AsIs) => {
+                Some(Indent::Spaces(width.unwrap_or(DEFAULT_INDENT_WIDTH)))
+            }
+            (None, Indent::Spaces(_)) => width.map(Indent::Spaces),
+            (None, Indent::AsIs) => None,
+        };
+        IndentSetting { indent, tab_stop }
+    }
+}
+
+// Returns key and value of `key = value` line. Key is case-insensitive
+fn property(line: &str) -> Option<(String, &str)> {
+    let (key, value) = line.split_once('=')?;
+    Some((key.trim().to_ascii_lowercase(), value.trim()))
+}
+
+fn is_comment(line: &str) -> bool {
+    line.is_empty() || line.starts_with('#') || line.starts_with(';')
+}
+
+fn is_root(content: &str) -> bool {
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            return false; // `root` must be in preamble
+        }
+        if is_comment(line) {
+            continue;
+        }
+        if let Some((key, value)) = property(line) {
+            if key == "root" {
+                return value.eq_ignore_ascii_case("true");
+            }
+        }
+    }
+    false
+}
+
+// Path of the file relative to the directory of `.editorconfig` separated with '/'
+fn relative_path(path: &Path, dir: &Path) -> String {
+    let rel = path.strip_prefix(dir).unwrap_or(path);
+    let parts: Vec<_> = rel.iter().map(|p| p.to_string_lossy()).collect();
+    parts.join("/")
+}
+
+fn apply_sections(content: &str, rel_path: &str, props: &mut HashMap<String, String>) {
+    let mut matched = false;
+    for line in content.lines().map(str::trim) {
+        if is_comment(line) {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            matched = section_matches(section, rel_path);
+            continue;
+        }
+        if !matched {
+            continue;
+        }
+        if let Some((key, value)) = property(line) {
+            if value.eq_ignore_ascii_case("unset") {
+                props.remove(&key);
+            } else {
+                props.insert(key, value.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+// Section name without '/' matches file name in any directory. Otherwise it matches path relative
+// to the directory of `.editorconfig`
+fn section_matches(section: &str, rel_path: &str) -> bool {
+    if section.contains('/') {
+        let section = section.strip_prefix('/').unwrap_or(section);
+        glob_match(section, rel_path)
+    } else {
+        let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        glob_match(section, name)
+    }
+}
+
+// Index of '}' closing the brace at start of `pat` considering nested braces
+fn closing_brace(pat: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in pat.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Split alternatives in braces by ',' at top level
+fn alternatives(inner: &str) -> Vec<&str> {
+    let mut alts = vec![];
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alts.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    alts.push(&inner[start..]);
+    alts
+}
+
+// Parse `{num1..num2}` range
+fn num_range(inner: &str) -> Option<(i64, i64)> {
+    let (start, end) = inner.split_once("..")?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+// Match character class such as `[abc]`, `[a-z]` or `[!abc]`. Returns whether the character
+// matches and the rest of pattern after the class
+fn match_class(pat: &str, c: char) -> (bool, &str) {
+    let end = pat.find(']').unwrap_or(pat.len());
+    let (class, rest) = (&pat[..end], pat.get(end + 1..).unwrap_or(""));
+    let (negated, class) = match class.strip_prefix('!') {
+        Some(class) => (true, class),
+        None => (false, class),
+    };
+    let chars: Vec<_> = class.chars().collect();
+    let mut matched = false;
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            matched |= (chars[i]..=chars[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= chars[i] == c;
+            i += 1;
+        }
+    }
+    (matched != negated, rest)
+}
+
+// Glob matching in EditorConfig: `*` matches any string except for '/', `**` matches any string,
+// `?` matches any character, `[name]`, `[!name]`, `{s1,s2}` and `{num1..num2}`
+fn glob_match(pat: &str, text: &str) -> bool {
+    let mut chars = pat.chars();
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return text.is_empty(),
+    };
+    let rest = chars.as_str();
+    match c {
+        '*' => {
+            let (rest, cross_dir) = match rest.strip_prefix('*') {
+                Some(rest) => (rest, true),
+                None => (rest, false),
+            };
+            for (i, c) in text.char_indices() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if c == '/' && !cross_dir {
+                    return false;
+                }
+            }
+            glob_match(rest, "")
+        }
+        '?' => match text.chars().next() {
+            Some(t) if t != '/' => glob_match(rest, &text[t.len_utf8()..]),
+            _ => false,
+        },
+        '[' if rest.contains(']') => match text.chars().next() {
+            Some(t) if t != '/' => {
+                let (matched, rest) = match_class(rest, t);
+                matched && glob_match(rest, &text[t.len_utf8()..])
+            }
+            _ => false,
+        },
+        '{' => {
+            let end = match closing_brace(pat) {
+                Some(end) => end,
+                None => return text.starts_with('{') && glob_match(rest, &text[1..]),
+            };
+            let (inner, rest) = (&pat[1..end], &pat[end + 1..]);
+            if let Some((start, end)) = num_range(inner) {
+                let (lo, hi) = (start.min(end), start.max(end));
+                let len = text
+                    .char_indices()
+                    .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+                    .map(|(i, _)| i)
+                    .unwrap_or(text.len());
+                return (1..=len).any(|n| match text[..n].parse::<i64>() {
+                    Ok(num) => (lo..=hi).contains(&num) && glob_match(rest, &text[n..]),
+                    Err(_) => false,
+                });
+            }
+            let alts = alternatives(inner);
+            if alts.len() == 1 {
+                // Braces without comma are matched literally
+                return text.starts_with('{') && glob_match(&pat[1..], &text[1..]);
+            }
+            alts.iter()
+                .any(|alt| glob_match(&format!("{}{}", alt, rest), text))
+        }
+        '\\' => match rest.chars().next() {
+            Some(e) => {
+                text.starts_with(e) && glob_match(&rest[e.len_utf8()..], &text[e.len_utf8()..])
+            }
+            None => text == "\\",
+        },
+        c => text.starts_with(c) && glob_match(rest, &text[c.len_utf8()..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn glob_patterns() {
+        for (pat, text, expected) in [
+            ("*.rs", "main.rs", true),
+            ("*.rs", "src/main.rs", false),
+            ("**.rs", "src/main.rs", true),
+            ("src/*", "src/a/b.rs", false),
+            ("src/**", "src/a/b.rs", true),
+            ("*", "", true),
+            ("?.c", "a.c", true),
+            ("?.c", "ab.c", false),
+            ("a?b", "a/b", false),
+            ("[a-c].txt", "b.txt", true),
+            ("[a-c].txt", "d.txt", false),
+            ("[!a-c].txt", "d.txt", true),
+            ("[!a-c].txt", "b.txt", false),
+            ("[xyz]", "y", true),
+            ("*.{c,h}", "a.h", true),
+            ("*.{c,h}", "a.rs", false),
+            ("{foo,{bar,baz}}.txt", "baz.txt", true),
+            ("{single}.txt", "{single}.txt", true),
+            ("{single}.txt", "single.txt", false),
+            ("file{1..3}.txt", "file2.txt", true),
+            ("file{1..3}.txt", "file0.txt", false),
+            ("file{1..3}.txt", "file4.txt", false),
+            ("file{1..3}.txt", "file10.txt", false),
+            ("file{3..1}.txt", "file2.txt", true),
+            ("f{-3..-1}", "f-2", true),
+            ("f{-3..-1}", "f-4", false),
+            ("f{-3..-1}", "f1", false),
+            ("f{-1..1}", "f0", true),
+            ("\\*.txt", "*.txt", true),
+            ("\\*.txt", "a.txt", false),
+            ("\\[a\\].txt", "[a].txt", true),
+            ("\\{a,b\\}", "{a,b}", true),
+            ("\\{a,b\\}", "a", false),
+        ] {
+            assert_eq!(glob_match(pat, text), expected, "{:?} for {:?}", pat, text);
+        }
+    }
+
+    #[test]
+    fn section_matches_file_name_or_path() {
+        for (section, path, expected) in [
+            ("*.rs", "src/main.rs", true),
+            ("main.rs", "src/main.rs", true),
+            ("src/*.rs", "src/main.rs", true),
+            ("/src/*.rs", "src/main.rs", true),
+            ("src/*.rs", "lib/src/main.rs", false),
+        ] {
+            assert_eq!(section_matches(section, path), expected, "{:?}", section);
+        }
+    }
+
+    #[test]
+    fn later_sections_override_and_unset() {
+        let content = "
+[*]
+indent_style = space
+indent_size = 2
+charset = latin1
+
+[*.c]
+indent_style = Tab
+charset = unset
+
+[*.h]
+indent_size = 8
+";
+        let mut props = HashMap::new();
+        apply_sections(content, "src/a.c", &mut props);
+        let expected: HashMap<_, _> = [("indent_size", "2"), ("indent_style", "tab")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(props, expected);
+    }
+
+    #[test]
+    fn root_stops_searching_parent_directories() {
+        let dir = env::temp_dir().join(format!("kiro-test-editorconfig-root-{}", process::id()));
+        let (child, grandchild) = (dir.join("child"), dir.join("child").join("grandchild"));
+        fs::create_dir_all(&grandchild).unwrap();
+        let path = grandchild.join("test.txt");
+
+        fs::write(dir.join(".editorconfig"), "[*]\ncharset = latin1\n").unwrap();
+        fs::write(child.join(".editorconfig"), "[*]\nend_of_line = crlf\n").unwrap();
+        fs::write(grandchild.join(".editorconfig"), "[*]\nindent_size = 3\n").unwrap();
+        let all = EditorConfig::load(&path);
+
+        fs::write(
+            child.join(".editorconfig"),
+            "root = true\n[*]\nend_of_line = crlf\n",
+        )
+        .unwrap();
+        let stopped = EditorConfig::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(all.charset, Some(Charset::Latin1));
+        assert_eq!(all.end_of_line, Some(LineEnding::CrLf));
+        assert_eq!(all.indent_size, Some(IndentSize::Width(3)));
+        assert_eq!(stopped.charset, None);
+        assert_eq!(stopped.end_of_line, Some(LineEnding::CrLf));
+        assert_eq!(stopped.indent_size, Some(IndentSize::Width(3)));
+    }
+}
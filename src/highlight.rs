@@ -1,4 +1,6 @@
+use std::cmp;
 use std::iter;
+use std::ops;
 
 use crate::language::Language;
 use crate::row::Row;
@@ -19,6 +21,7 @@ pub enum Highlight {
     SpecialVar,
     Search,
     Match,
+    MatchBracket,
 }
 
 impl Highlight {
@@ -39,6 +42,7 @@ impl Highlight {
             SpecialVar => Cyan,
             Search => OrangeBG,
             Match => YellowBG,
+            MatchBracket => GrayBG,
         }
     }
 }
@@ -522,6 +526,48 @@ impl SyntaxHighlight {
     }
 }
 
+// Comment syntax of each language. This is used for editing comments
+pub struct CommentSyntax {
+    pub line: Option<&'static str>,
+    pub block: Option<(&'static str, &'static str)>,
+}
+
+impl CommentSyntax {
+    pub fn for_lang(lang: Language) -> Self {
+        let syntax = SyntaxHighlight::for_lang(lang);
+        Self {
+            line: syntax.line_comment,
+            block: syntax.block_comment,
+        }
+    }
+}
+
+// Returns closing character which is automatically inserted after the opening character
+pub fn closing_pair(lang: Language, open: char) -> Option<char> {
+    let syntax = SyntaxHighlight::for_lang(lang);
+    match open {
+        _ if syntax.lang == Language::Plain => None,
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        c if syntax.string_quotes.contains(&c) => Some(c),
+        _ => None,
+    }
+}
+
+// Returns the counterpart of the bracket and whether the bracket is an opening one
+fn bracket_partner(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 enum NumLit {
     Digit,
@@ -668,7 +714,7 @@ impl<'a> Highlighter<'a> {
         fn iter_words<'a>(
             words: &'a [&'a str],
             hl: Highlight,
-        ) -> impl Iterator<Item = (&&'a str, Highlight)> {
+        ) -> impl Iterator<Item = (&'a &'a str, Highlight)> {
             words.iter().zip(iter::repeat(hl))
         }
 
@@ -682,7 +728,7 @@ impl<'a> Highlighter<'a> {
                 .chain(iter_words(self.syntax.special_vars, SpecialVar))
                 .find(|(k, _)| *k == ident);
 
-            let definition = keyword.or_else(|| {
+            let definition = keyword.or({
                 if self.after_def_keyword {
                     Some((ident, Highlight::Definition))
                 } else {
@@ -690,7 +736,7 @@ impl<'a> Highlighter<'a> {
                 }
             });
 
-            if keyword.is_some() && self.syntax.definition_keywords.contains(&ident) {
+            if keyword.is_some() && self.syntax.definition_keywords.contains(ident) {
                 self.after_def_keyword = true;
             }
 
@@ -847,7 +893,7 @@ impl<'a> Highlighter<'a> {
                     // while statement always consume one character at top. Eat input chars considering that.
                     iter.nth(len.saturating_sub(2));
                 }
-                ParseStep::Ahead(len) if len == 1 => { /* Go next */ }
+                ParseStep::Ahead(1) => { /* Go next */ }
                 ParseStep::Ahead(_) => unreachable!(),
                 ParseStep::Break => break,
             }
@@ -880,6 +926,8 @@ pub struct Highlighting {
     pub lines: Vec<Vec<Highlight>>, // TODO: One item per one character
     previous_bottom_of_screen: usize,
     matched: Vec<RegionHighlight>,
+    // Positions of the bracket at cursor and its matching bracket
+    bracket_match: Option<[(usize, usize); 2]>,
     syntax: &'static SyntaxHighlight,
 }
 
@@ -890,6 +938,7 @@ impl Default for Highlighting {
             lines: vec![],
             previous_bottom_of_screen: 0,
             matched: vec![],
+            bracket_match: None,
             syntax: &PLAIN_SYNTAX,
         }
     }
@@ -902,17 +951,78 @@ impl Highlighting {
             lines: rows
                 .iter()
                 .map(|r| {
-                    iter::repeat(Highlight::Normal)
-                        .take(r.render_text().chars().count()) // TODO: One item per one character
+                    std::iter::repeat_n(Highlight::Normal, r.render_text().chars().count()) // TODO: One item per one character
                         .collect()
                 })
                 .collect(),
             previous_bottom_of_screen: 0,
             matched: vec![],
+            bracket_match: None,
             syntax: SyntaxHighlight::for_lang(lang),
         }
     }
 
+    // Returns true when the position is inside a string literal or a comment
+    pub fn is_literal_at(&self, rows: &[Row], (x, y): (usize, usize)) -> bool {
+        if x == 0 {
+            // At head of line, block comment or string literal may continue from previous line
+            return y > 0 && self.literal_continues(rows, y - 1);
+        }
+        let (row, line) = match (rows.get(y), self.lines.get(y)) {
+            (Some(row), Some(line)) => (row, line),
+            _ => return false,
+        };
+        let idx = row.render_idx_from_cx(x);
+        let prev = line.get(idx - 1);
+        let next = line.get(idx);
+        match prev {
+            Some(Highlight::Comment) => match self.syntax.block_comment {
+                Some((_, end)) => !row[..x].ends_with(end),
+                None => true,
+            },
+            Some(Highlight::String) => match next {
+                Some(Highlight::String) => true,
+                // At end of line, check the string literal is not closed yet
+                None => !self.syntax.string_quotes.contains(&row.char_at(x - 1)),
+                Some(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    // Returns true when block comment or string literal is not closed at the end of the line
+    fn literal_continues(&self, rows: &[Row], y: usize) -> bool {
+        let (row, line) = match (rows.get(y), self.lines.get(y)) {
+            (Some(row), Some(line)) => (row, line),
+            _ => return false,
+        };
+        match line.last() {
+            Some(Highlight::Comment) => {
+                let end = match self.syntax.block_comment {
+                    Some((_, end)) => end,
+                    None => return false,
+                };
+                // Trailing comment may be a line comment
+                let start = line
+                    .iter()
+                    .rposition(|&hl| hl != Highlight::Comment)
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                let comment: String = row.render_text().chars().skip(start).collect();
+                let is_line_comment = match self.syntax.line_comment {
+                    Some(leader) => comment.starts_with(leader),
+                    None => false,
+                };
+                !is_line_comment && !comment.ends_with(end)
+            }
+            Some(Highlight::String) => match row.buffer().chars().last() {
+                Some(c) => !self.syntax.string_quotes.contains(&c),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
     pub fn lang_changed(&mut self, new_lang: Language) {
         if self.syntax.lang == new_lang {
             return;
@@ -939,10 +1049,10 @@ impl Highlighting {
             return;
         }
 
-        let mut highlighter = Highlighter::new(&self.syntax);
+        let mut highlighter = Highlighter::new(self.syntax);
 
         self.lines.resize_with(rows.len(), Default::default);
-        for (y, ref row) in rows.iter().enumerate().take(bottom_of_screen) {
+        for (y, row) in rows.iter().enumerate().take(bottom_of_screen) {
             let row = row.render_text();
             self.lines[y].resize(row.chars().count(), Highlight::Normal); // TODO: One item per one character
 
@@ -954,11 +1064,116 @@ impl Highlighting {
         // TODO: Move logic to highlighter rather than overwriting highlights after.
         // Give self.matched to Highlighter::new() and it checks each cell should be highlighted as match
         self.highlight_match(None);
+        self.highlight_bracket_match(rows, Highlight::MatchBracket);
 
         self.needs_update = false;
         self.previous_bottom_of_screen = bottom_of_screen;
     }
 
+    fn highlight_bracket_match(&mut self, rows: &[Row], hl: Highlight) {
+        for &(x, y) in self.bracket_match.iter().flatten() {
+            if let (Some(row), Some(line)) = (rows.get(y), self.lines.get_mut(y)) {
+                if let Some(cell) = line.get_mut(row.render_idx_from_cx(x)) {
+                    *cell = hl;
+                }
+            }
+        }
+    }
+
+    fn is_code_at(&self, y: usize, idx: usize) -> bool {
+        !matches!(
+            self.lines.get(y).and_then(|l| l.get(idx)),
+            Some(Highlight::String) | Some(Highlight::Comment) | Some(Highlight::Char)
+        )
+    }
+
+    // Returns positions of the bracket at (or just before) the cursor and its matching bracket.
+    // Brackets in string literals and comments are skipped by checking highlights of lines. Only
+    // `lines` are searched not to scan entire buffer on each render. Lines after the bottom of
+    // highlighted area are not searched either.
+    pub fn find_bracket_pair(
+        &self,
+        rows: &[Row],
+        (cx, cy): (usize, usize),
+        lines: ops::Range<usize>,
+    ) -> Option<[(usize, usize); 2]> {
+        let row = rows.get(cy)?;
+        let x = [Some(cx), cx.checked_sub(1)]
+            .iter()
+            .flatten()
+            .copied()
+            .find(|&x| {
+                row.char_at_checked(x).and_then(bracket_partner).is_some()
+                    && self.is_code_at(cy, row.render_idx_from_cx(x))
+            })?;
+        let bracket = row.char_at(x);
+        let (partner, forward) = bracket_partner(bracket)?;
+        let start = row.render_idx_from_cx(x);
+
+        let mut depth = 0;
+        let mut visit = |c: char, idx: usize, y: usize| {
+            if c != bracket && c != partner || !self.is_code_at(y, idx) {
+                None
+            } else if c == bracket {
+                depth += 1;
+                None
+            } else if depth == 0 {
+                Some((rows[y].cx_from_render_idx(idx), y))
+            } else {
+                depth -= 1;
+                None
+            }
+        };
+
+        let found = if forward {
+            let bottom = cmp::min(rows.len(), self.previous_bottom_of_screen);
+            let bottom = cmp::min(bottom, lines.end);
+            (cy..bottom).find_map(|y| {
+                let skip = if y == cy { start + 1 } else { 0 };
+                let mut chars = rows[y].render_text().chars().enumerate().skip(skip);
+                chars.find_map(|(idx, c)| visit(c, idx, y))
+            })
+        } else {
+            (lines.start..=cy).rev().find_map(|y| {
+                let text = rows[y].render_text();
+                let len = text.chars().count();
+                let skip = if y == cy { len - start } else { 0 };
+                let mut chars = text.chars().rev().zip((0..len).rev()).skip(skip);
+                chars.find_map(|(c, idx)| visit(c, idx, y))
+            })
+        };
+
+        found.map(|pos| [(x, cy), pos])
+    }
+
+    // Update highlights of the bracket at cursor and its matching bracket. When they are changed,
+    // it returns the first line which needs to be redrawn.
+    pub fn update_bracket_match(
+        &mut self,
+        rows: &[Row],
+        cursor: (usize, usize),
+        lines: ops::Range<usize>,
+    ) -> Option<usize> {
+        let pair = self.find_bracket_pair(rows, cursor, lines);
+        if pair == self.bracket_match {
+            return None;
+        }
+        if self.syntax.lang == Language::Plain {
+            // Back to normal color since highlighting is skipped on 'plain' syntax
+            self.highlight_bracket_match(rows, Highlight::Normal);
+        }
+        let dirty_start = self
+            .bracket_match
+            .iter()
+            .chain(pair.iter())
+            .flatten()
+            .map(|&(_, y)| y)
+            .min();
+        self.bracket_match = pair;
+        self.needs_update = true;
+        dirty_start
+    }
+
     pub fn set_matches(&mut self, matches: Vec<RegionHighlight>) {
         self.clear_previous_match();
         self.matched = matches;
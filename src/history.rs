@@ -26,7 +26,7 @@ impl History {
             return false; // Do nothing when no change was added
         }
 
-        let diffs = mem::replace(&mut self.ongoing, vec![]);
+        let diffs = mem::take(&mut self.ongoing);
 
         if self.entries.len() == MAX_ENTRIES {
             self.entries.pop_front();
@@ -47,32 +47,41 @@ impl History {
         diffs: I,
         which: UndoRedo,
         rows: &mut Vec<Row>,
+        tab_stop: usize,
     ) -> (usize, usize, usize) {
-        diffs.fold((0, 0, usize::max_value()), |(_, _, dirty_start), diff| {
-            let (x, y) = diff.apply(rows, which);
+        diffs.fold((0, 0, usize::MAX), |(_, _, dirty_start), diff| {
+            let (x, y) = diff.apply(rows, which, tab_stop);
             (x, y, cmp::min(dirty_start, y))
         })
     }
 
-    pub fn undo(&mut self, rows: &mut Vec<Row>) -> Option<(usize, usize, usize, bool)> {
+    pub fn undo(
+        &mut self,
+        rows: &mut Vec<Row>,
+        tab_stop: usize,
+    ) -> Option<(usize, usize, usize, bool)> {
         let edited = self.finish_ongoing_edit();
         if self.index == 0 {
             return None;
         }
         self.index -= 1;
         let i = self.entries[self.index].iter().rev();
-        let (x, y, dirty_start) = Self::apply_diffs(i, UndoRedo::Undo, rows);
+        let (x, y, dirty_start) = Self::apply_diffs(i, UndoRedo::Undo, rows, tab_stop);
         Some((x, y, dirty_start, edited))
     }
 
-    pub fn redo(&mut self, rows: &mut Vec<Row>) -> Option<(usize, usize, usize, bool)> {
+    pub fn redo(
+        &mut self,
+        rows: &mut Vec<Row>,
+        tab_stop: usize,
+    ) -> Option<(usize, usize, usize, bool)> {
         let edited = self.finish_ongoing_edit();
         if self.index == self.entries.len() {
             return None;
         }
         self.index += 1;
         let i = self.entries[self.index - 1].iter();
-        let (x, y, dirty_start) = Self::apply_diffs(i, UndoRedo::Redo, rows);
+        let (x, y, dirty_start) = Self::apply_diffs(i, UndoRedo::Redo, rows, tab_stop);
         Some((x, y, dirty_start, edited))
     }
 }
@@ -81,7 +81,9 @@ pub enum KeySeq {
     HomeKey,
     EndKey,
     DeleteKey,
+    InsertKey,
     Cursor(usize, usize), // Pseudo key (x, y)
+    Paste(String),        // Pseudo key for text pasted while bracketed paste mode
 }
 
 impl fmt::Display for KeySeq {
@@ -102,7 +104,9 @@ impl fmt::Display for KeySeq {
             HomeKey => write!(f, "HOME"),
             EndKey => write!(f, "END"),
             DeleteKey => write!(f, "DELETE"),
+            InsertKey => write!(f, "INSERT"),
             Cursor(r, c) => write!(f, "CURSOR({},{})", r, c),
+            Paste(_) => write!(f, "PASTE"),
         }
     }
 }
@@ -152,6 +156,33 @@ impl fmt::Display for InputSeq {
     }
 }
 
+// Read bytes of pasted text until the end of paste. `read_byte` returns None when reading timed out.
+// Large text pasted via slow connection (e.g. ssh) may not arrive within one tick, so timeout does
+// not mean the end of paste.
+fn read_paste_bytes<F: FnMut() -> Result<Option<u8>>>(mut read_byte: F) -> Result<Vec<u8>> {
+    const PASTE_END: &[u8] = b"\x1b[201~";
+    // Give up waiting when no byte arrives for about 10 seconds not to hang when the end of paste
+    // was lost
+    const MAX_IDLE_TICKS: usize = 100;
+
+    let mut buf = vec![];
+    let mut idle_ticks = 0;
+    while idle_ticks < MAX_IDLE_TICKS {
+        match read_byte()? {
+            Some(b) => {
+                idle_ticks = 0;
+                buf.push(b);
+                if buf.ends_with(PASTE_END) {
+                    buf.truncate(buf.len() - PASTE_END.len());
+                    break;
+                }
+            }
+            None => idle_ticks += 1,
+        }
+    }
+    Ok(buf)
+}
+
 pub struct InputSequences {
     stdin: StdinRawMode,
 }
@@ -166,6 +197,23 @@ impl InputSequences {
         })
     }
 
+    fn read_pasted_text(&mut self) -> Result<InputSeq> {
+        // Pasted text is surrounded with \x1b[200~ and \x1b[201~ while bracketed paste mode.
+        // Bytes in the text are read as-is without decoding them as keys.
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Bracketed-Paste-Mode
+        let buf = read_paste_bytes(|| self.read_byte())?;
+
+        // Terminals send \r as newline on pasting text. Other control characters cannot be put in
+        // text buffer so they are removed
+        let text = String::from_utf8_lossy(&buf)
+            .replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .chars()
+            .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+            .collect();
+        Ok(InputSeq::new(KeySeq::Paste(text)))
+    }
+
     fn decode_escape_sequence(&mut self) -> Result<InputSeq> {
         use KeySeq::*;
 
@@ -174,6 +222,8 @@ impl InputSequences {
         // TODO?: Should we consider sequences not starting with '['?
         match self.read_byte()? {
             Some(b'[') => { /* fall thought */ }
+            // Alt-Backspace => \x1b\x7f
+            Some(0x7f) => return Ok(InputSeq::alt(Key(0x7f))),
             Some(b) if b.is_ascii_control() => return Ok(InputSeq::new(Key(0x1b))), // Ignore control characters after ESC
             Some(b) => {
                 // Alt key is sent as ESC prefix (e.g. Alt-A => \x1b\x61
@@ -226,8 +276,12 @@ impl InputSequences {
                     b'D' => LeftKey,
                     _ => unreachable!(),
                 };
-                let ctrl = args.next() == Some(b"1") && args.next() == Some(b"5");
-                let alt = false;
+                // e.g. M-<UP> => \x1b[1;3A
+                let (ctrl, alt) = match (args.next(), args.next()) {
+                    (Some(b"1"), Some(b"5")) => (true, false),
+                    (Some(b"1"), Some(b"3")) => (false, true),
+                    _ => (false, false),
+                };
                 Ok(InputSeq { key, ctrl, alt })
             }
             b'~' => {
@@ -237,7 +291,9 @@ impl InputSequences {
                     Some(b"6") => Ok(InputSeq::new(PageDownKey)),
                     Some(b"1") | Some(b"7") => Ok(InputSeq::new(HomeKey)),
                     Some(b"4") | Some(b"8") => Ok(InputSeq::new(EndKey)),
+                    Some(b"2") => Ok(InputSeq::new(InsertKey)),
                     Some(b"3") => Ok(InputSeq::new(DeleteKey)),
+                    Some(b"200") => self.read_pasted_text(),
                     _ => Ok(InputSeq::new(Unidentified)),
                 }
             }
@@ -325,3 +381,30 @@ impl Iterator for InputSequences {
         Some(self.read_seq())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes read from terminal. None represents a timeout tick
+    fn dummy_reader(bytes: Vec<Option<u8>>) -> impl FnMut() -> Result<Option<u8>> {
+        let mut bytes = bytes.into_iter();
+        move || Ok(bytes.next().flatten())
+    }
+
+    #[test]
+    fn read_paste_across_timeout_ticks() {
+        let mut bytes = vec![Some(b'a'), None, None, Some(b'\r'), None, Some(b'b')];
+        bytes.extend(b"\x1b[201~".iter().copied().map(Some));
+        bytes.push(Some(b'x'));
+        let mut read = dummy_reader(bytes);
+        assert_eq!(read_paste_bytes(&mut read).unwrap(), b"a\rb");
+        assert_eq!(read().unwrap(), Some(b'x')); // Bytes after the end of paste are not consumed
+    }
+
+    #[test]
+    fn read_paste_without_end() {
+        let read = dummy_reader(vec![Some(b'a'), Some(b'b')]);
+        assert_eq!(read_paste_bytes(read).unwrap(), b"ab");
+    }
+}
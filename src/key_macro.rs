@@ -0,0 +1,182 @@
+use crate::error::Result;
+use crate::input::{InputSeq, KeySeq};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Wrap input sequences to record keys consumed by editor and to replay recorded keys. Keys are
+// recorded here so that keys consumed by prompts are also recorded and replayed.
+pub struct MacroInput<I: Iterator<Item = Result<InputSeq>>> {
+    input: I,
+    recording: Option<Vec<InputSeq>>,
+    replaying: VecDeque<InputSeq>,
+}
+
+impl<I: Iterator<Item = Result<InputSeq>>> MacroInput<I> {
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            recording: None,
+            replaying: VecDeque::new(),
+        }
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recording = Some(vec![]);
+    }
+
+    // Returns recorded keys. Returns None when recording has not started
+    pub fn stop_recording(&mut self) -> Option<Vec<InputSeq>> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn replay<K: IntoIterator<Item = InputSeq>>(&mut self, keys: K) {
+        self.replaying.extend(keys);
+    }
+
+    // Returns the next key to be replayed. Unlike next(), it never reads actual input
+    pub fn next_replayed(&mut self) -> Option<InputSeq> {
+        self.replaying.pop_front()
+    }
+
+    // Push back keys which were read but not handled so that they are read again in order
+    pub fn unread(&mut self, keys: Vec<InputSeq>) {
+        for key in keys.into_iter().rev() {
+            self.replaying.push_front(key);
+        }
+    }
+
+    pub fn cancel_replay(&mut self) {
+        self.replaying.clear();
+    }
+}
+
+impl<I: Iterator<Item = Result<InputSeq>>> Iterator for MacroInput<I> {
+    type Item = Result<InputSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(seq) = self.replaying.pop_front() {
+            return Some(Ok(seq));
+        }
+        let seq = self.input.next()?;
+        if let (Ok(seq), Some(recorded)) = (&seq, &mut self.recording) {
+            // Pseudo keys are not caused by user's key input
+            if !matches!(seq.key, KeySeq::Unidentified | KeySeq::Cursor(..)) {
+                recorded.push(seq.clone());
+            }
+        }
+        Some(seq)
+    }
+}
+
+fn encode_key(seq: &InputSeq) -> String {
+    use KeySeq::*;
+
+    let mut line = String::new();
+    if seq.ctrl {
+        line.push_str("C-");
+    }
+    if seq.alt {
+        line.push_str("M-");
+    }
+    match &seq.key {
+        Key(b) => line.push_str(&format!("0x{:02x}", b)),
+        Utf8Key(c) => line.push_str(&format!("U+{:04X}", *c as u32)),
+        Paste(text) => {
+            line.push_str("PASTE:");
+            for c in text.chars() {
+                match c {
+                    '\\' => line.push_str("\\\\"),
+                    '\n' => line.push_str("\\n"),
+                    c => line.push(c),
+                }
+            }
+        }
+        key => line.push_str(&key.to_string()),
+    }
+    line
+}
+
+fn decode_key(line: &str) -> Option<InputSeq> {
+    use KeySeq::*;
+
+    let (ctrl, line) = match line.strip_prefix("C-") {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (alt, line) = match line.strip_prefix("M-") {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let key = if let Some(hex) = line.strip_prefix("0x") {
+        Key(u8::from_str_radix(hex, 16).ok()?)
+    } else if let Some(hex) = line.strip_prefix("U+") {
+        Utf8Key(std::char::from_u32(u32::from_str_radix(hex, 16).ok()?)?)
+    } else if let Some(escaped) = line.strip_prefix("PASTE:") {
+        let mut text = String::with_capacity(escaped.len());
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next()? {
+                    'n' => text.push('\n'),
+                    c => text.push(c),
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        Paste(text)
+    } else {
+        match line {
+            "LEFT" => LeftKey,
+            "RIGHT" => RightKey,
+            "UP" => UpKey,
+            "DOWN" => DownKey,
+            "PAGEUP" => PageUpKey,
+            "PAGEDOWN" => PageDownKey,
+            "HOME" => HomeKey,
+            "END" => EndKey,
+            "DELETE" => DeleteKey,
+            "INSERT" => InsertKey,
+            _ => return None,
+        }
+    };
+
+    Some(InputSeq { key, ctrl, alt })
+}
+
+// Save keys to the file. Each line represents one key such as 'C-0x61' (Ctrl-A)
+pub fn save<P: AsRef<Path>>(path: P, keys: &[InputSeq]) -> std::result::Result<String, String> {
+    let path = path.as_ref();
+    let f = File::create(path).map_err(|e| format!("Could not save macro: {}", e))?;
+    let mut f = io::BufWriter::new(f);
+    for key in keys {
+        writeln!(f, "{}", encode_key(key)).map_err(|e| format!("Could not write macro: {}", e))?;
+    }
+    f.flush()
+        .map_err(|e| format!("Could not flush macro: {}", e))?;
+    Ok(format!(
+        "Saved macro ({} keys) to {}",
+        keys.len(),
+        path.display()
+    ))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> std::result::Result<Vec<InputSeq>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Could not load macro: {}", e))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(idx, line)| {
+            decode_key(line)
+                .ok_or_else(|| format!("Invalid key '{}' at line {} of macro", line, idx + 1))
+        })
+        .collect()
+}
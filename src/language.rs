@@ -1,9 +1,108 @@
 use std::ffi::OsStr;
 use std::path::Path;
 
+// Max number of spaces for one level of indentation and max width of tab stop
+pub const MAX_INDENT_WIDTH: usize = 16;
+const SPACES: &str = "                ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Indent {
     AsIs,
-    Fixed(&'static str),
+    Spaces(usize),
+}
+
+impl Indent {
+    // One level of indentation
+    pub fn unit(&self) -> &'static str {
+        match *self {
+            Indent::AsIs => "\t",
+            Indent::Spaces(width) => &SPACES[..width],
+        }
+    }
+}
+
+// Indentation and tab stop set by user. None means that the default value is used
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IndentSetting {
+    pub indent: Option<Indent>,
+    pub tab_stop: Option<usize>,
+}
+
+impl IndentSetting {
+    // Parse 'STYLE[:TAB_STOP]' where STYLE is 'tab' or number of spaces (e.g. 'tab:4', '2'). STYLE
+    // can be omitted to set only tab stop (e.g. ':4')
+    pub fn parse(input: &str) -> Option<Self> {
+        fn width(s: &str) -> Option<usize> {
+            s.trim()
+                .parse()
+                .ok()
+                .filter(|w| (1..=MAX_INDENT_WIDTH).contains(w))
+        }
+
+        let (style, tab_stop) = match input.split_once(':') {
+            Some((style, tab_stop)) => (style.trim(), Some(width(tab_stop)?)),
+            None => (input.trim(), None),
+        };
+        let indent = match style {
+            "" if tab_stop.is_some() => None,
+            "tab" | "tabs" => Some(Indent::AsIs),
+            spaces => Some(Indent::Spaces(width(spaces)?)),
+        };
+        Some(Self { indent, tab_stop })
+    }
+
+    // Values set in `other` take precedence
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            indent: other.indent.or(self.indent),
+            tab_stop: other.tab_stop.or(self.tab_stop),
+        }
+    }
+}
+
+// Indentation settings given globally and per language
+#[derive(Default)]
+pub struct IndentConfig {
+    global: IndentSetting,
+    langs: Vec<(Language, IndentSetting)>,
+}
+
+impl IndentConfig {
+    // Add setting '[LANG=]STYLE[:TAB_STOP]'. Setting with language name only applies to buffers of
+    // the language
+    pub fn add(&mut self, arg: &str) -> Result<(), String> {
+        let (lang, setting) = match arg.split_once('=') {
+            Some((name, setting)) => match Language::from_name(name.trim()) {
+                Some(lang) => (Some(lang), setting),
+                None => return Err(format!("Unknown language '{}'", name)),
+            },
+            None => (None, arg),
+        };
+        let setting = IndentSetting::parse(setting)
+            .ok_or_else(|| format!("Invalid indentation '{}'", setting))?;
+        match lang {
+            Some(lang) => match self.langs.iter_mut().find(|(l, _)| *l == lang) {
+                Some((_, s)) => *s = s.merge(setting),
+                None => self.langs.push((lang, setting)),
+            },
+            None => self.global = self.global.merge(setting),
+        }
+        Ok(())
+    }
+
+    pub fn setting(&self, lang: Language) -> IndentSetting {
+        match self.langs.iter().find(|(l, _)| *l == lang) {
+            Some((_, setting)) => self.global.merge(*setting),
+            None => self.global,
+        }
+    }
+}
+
+fn first_word(line: &str) -> &str {
+    line.trim_start()
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or("")
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -47,15 +146,94 @@ impl Language {
         }
     }
 
+    // Characters which are a part of word in addition to alphanumeric characters and '_'
+    pub fn word_chars(self) -> &'static [char] {
+        use Language::*;
+        match self {
+            JavaScript => &['$'],
+            Julia => &['!'],
+            Plain | C | Rust | Go | Cpp | Python => &[],
+        }
+    }
+
     pub fn indent(self) -> Indent {
         use Language::*;
         match self {
             Plain | Go => Indent::AsIs,
-            C | Rust | Cpp | Python | Julia => Indent::Fixed("    "),
-            JavaScript => Indent::Fixed("  "),
+            C | Rust | Cpp | Python | Julia => Indent::Spaces(4),
+            JavaScript => Indent::Spaces(2),
+        }
+    }
+
+    // Returns whether the language has blocks enclosed with brackets. Closing bracket at start of
+    // line dedents the line only in such languages. In other languages, indentation is significant
+    // or chosen by the writer so it should not be changed more than needed.
+    pub fn has_blocks(self) -> bool {
+        use Language::*;
+        match self {
+            C | Rust | JavaScript | Go | Cpp => true,
+            Plain | Python | Julia => false,
         }
     }
 
+    // Returns whether lines after the line should be indented one more level
+    pub fn opens_block(self, line: &str) -> bool {
+        let line = line.trim_end();
+        if line.ends_with(&['{', '(', '['][..]) {
+            return true;
+        }
+        match self {
+            Language::Python => line.ends_with(':'),
+            Language::Julia => {
+                const OPENERS: &[&str] = &[
+                    "function",
+                    "if",
+                    "elseif",
+                    "else",
+                    "for",
+                    "while",
+                    "begin",
+                    "let",
+                    "struct",
+                    "mutable",
+                    "module",
+                    "baremodule",
+                    "macro",
+                    "quote",
+                    "try",
+                    "catch",
+                    "finally",
+                ];
+                let mut words = line.split_whitespace();
+                OPENERS.contains(&first_word(line)) && words.next_back() != Some("end")
+                    || line.split_whitespace().any(|w| w == "do")
+            }
+            _ => false,
+        }
+    }
+
+    // Returns whether the line should be indented one level less than its previous line
+    pub fn closes_block(self, line: &str) -> bool {
+        let line = line.trim_start();
+        if line.starts_with(&['}', ')', ']'][..]) {
+            return true;
+        }
+        let closers: &[&str] = match self {
+            Language::Python => &["elif", "else", "except", "finally"],
+            Language::Julia => &["end", "elseif", "else", "catch", "finally"],
+            _ => return false,
+        };
+        closers.contains(&first_word(line))
+    }
+
+    pub fn from_name(name: &str) -> Option<Language> {
+        use Language::*;
+        [Plain, C, Rust, JavaScript, Go, Cpp, Python, Julia]
+            .iter()
+            .copied()
+            .find(|lang| lang.name().eq_ignore_ascii_case(name))
+    }
+
     pub fn detect<P: AsRef<Path>>(path: P) -> Language {
         use Language::*;
         if let Some(ext) = path.as_ref().extension().and_then(OsStr::to_str) {
@@ -10,22 +10,25 @@
 
 mod edit_diff;
 mod editor;
+mod editorconfig;
 mod error;
 mod highlight;
 mod history;
 mod input;
+mod key_macro;
 mod language;
 mod prompt;
 mod row;
 mod screen;
+mod shell;
 mod signal;
 mod status_bar;
 mod term_color;
 mod text_buffer;
 
-pub use editor::Editor;
+pub use editor::{Editor, DEFAULT_FILL_COLUMN};
 pub use error::{Error, Result};
 pub use input::{InputSeq, KeySeq, StdinRawMode};
-pub use language::Language;
+pub use language::{IndentConfig, Language};
 pub use screen::{Screen, HELP, VERSION};
 pub use text_buffer::{Lines, TextBuffer};
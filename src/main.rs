@@ -2,9 +2,12 @@
 //   Build Your Own Text Editor: https://viewsourcecode.org/snaptoken/kilo/index.html
 //   VT100 User Guide: https://vt100.net/docs/vt100-ug/chapter3.html
 use getopts::Options;
-use kiro_editor::{self as kiro, Editor, StdinRawMode, HELP, VERSION};
+use kiro_editor::{
+    self as kiro, Editor, IndentConfig, StdinRawMode, DEFAULT_FILL_COLUMN, HELP, VERSION,
+};
 use std::env;
 use std::io;
+use std::path::Path;
 use std::process::exit;
 
 #[global_allocator]
@@ -20,7 +23,10 @@ start to write a new text.
 Help can show up with key mapping Ctrl-?.
 
 Usage:
-    {prog} [options] [FILES...]
+    {prog} [options] [[+LINE] FILE[:LINE[:COL]]...]
+
+Cursor is put at the LINE and COL when they are specified with a file like
+'{prog} src/main.rs:42:7' or '{prog} +42 src/main.rs'.
 
 Mappings:
     {maps}",
@@ -30,10 +36,69 @@ Mappings:
     println!("{}", opts.usage(&description));
 }
 
-fn edit(files: Vec<String>) -> kiro::Result<()> {
+// File path with optional cursor position (line, column)
+type FileArg = (String, Option<(usize, usize)>);
+
+fn parse_position(pos: &str) -> Option<(usize, usize)> {
+    let mut nums = pos.split(':').map(|s| s.parse::<usize>().ok());
+    let line = nums.next()??;
+    let col = nums.next().unwrap_or(Some(1))?;
+    if nums.next().is_some() {
+        return None;
+    }
+    Some((line, col))
+}
+
+// Parse 'path:line:col' or 'path:line'. A path which exists as-is is not parsed since ':' may be
+// a part of file name
+fn parse_file_arg(arg: String) -> FileArg {
+    if Path::new(&arg).exists() {
+        return (arg, None);
+    }
+    for (idx, _) in arg.match_indices(':') {
+        if idx == 0 {
+            continue;
+        }
+        if let Some(pos) = parse_position(&arg[idx + 1..]) {
+            return (arg[..idx].to_string(), Some(pos));
+        }
+    }
+    (arg, None)
+}
+
+fn parse_file_args(args: Vec<String>) -> Result<Vec<FileArg>, String> {
+    let mut files = vec![];
+    let mut line = None;
+    for arg in args {
+        if let Some(pos) = arg.strip_prefix('+') {
+            match parse_position(pos) {
+                Some(pos) => line = Some(pos),
+                None => return Err(format!("Invalid position '{}'", arg)),
+            }
+            continue;
+        }
+        let (path, pos) = parse_file_arg(arg);
+        files.push((path, line.take().or(pos)));
+    }
+    if let Some((line, _)) = line {
+        return Err(format!("No file is specified for +{}", line));
+    }
+    Ok(files)
+}
+
+fn edit(files: Vec<FileArg>, fill_column: usize, indent: IndentConfig) -> kiro::Result<()> {
     // TODO: Read input from stdin before start
     let input = StdinRawMode::new()?.input_keys();
-    Editor::open(input, io::stdout(), None, &files)?.edit()
+    let paths: Vec<_> = files.iter().map(|(path, _)| path).collect();
+    let mut editor = Editor::open(input, io::stdout(), None, &paths)?;
+    editor.set_fill_column(fill_column);
+    editor.set_indent_config(indent);
+    for (idx, (_, pos)) in files.iter().enumerate() {
+        if let Some((line, col)) = pos {
+            editor.set_cursor_position(idx, *line, *col);
+        }
+    }
+    editor.edit()
 }
 
 fn main() {
@@ -43,6 +108,22 @@ fn main() {
     let mut opts = Options::new();
     opts.optflag("v", "version", "Print version");
     opts.optflag("h", "help", "Print this help");
+    opts.optopt(
+        "",
+        "fill-column",
+        &format!(
+            "Column at which paragraphs are wrapped by Alt-q (default: {})",
+            DEFAULT_FILL_COLUMN
+        ),
+        "NUM",
+    );
+    opts.optmulti(
+        "",
+        "indent",
+        "Indentation by 'tab' or number of spaces, optionally followed by ':' and width of tab \
+         stop. LANG= prefix applies it only to the language (e.g. 2, tab:4, c=tab:8, rust=:4)",
+        "[LANG=]STYLE[:TAB_STOP]",
+    );
 
     let matches = match opts.parse(argv) {
         Ok(m) => m,
@@ -62,8 +143,90 @@ fn main() {
         return;
     }
 
-    if let Err(err) = edit(matches.free) {
+    let fill_column = match matches.opt_get_default("fill-column", DEFAULT_FILL_COLUMN) {
+        Ok(col) => col,
+        Err(e) => {
+            eprintln!("Error: Invalid --fill-column: {}", e);
+            exit(1);
+        }
+    };
+
+    let mut indent = IndentConfig::default();
+    for arg in matches.opt_strs("indent") {
+        if let Err(e) = indent.add(&arg) {
+            eprintln!("Error: Invalid --indent: {}", e);
+            exit(1);
+        }
+    }
+
+    let files = match parse_file_args(matches.free) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error: {}. Please see --help for more details", e);
+            exit(1);
+        }
+    };
+
+    if let Err(err) = edit(files, fill_column, indent) {
         eprintln!("Error: {}", err);
         exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_file_args_with_positions() {
+        for (input, expected) in [
+            (&["foo.rs"][..], vec![("foo.rs", None)]),
+            (&["foo.rs:42"], vec![("foo.rs", Some((42, 1)))]),
+            (&["foo.rs:42:7"], vec![("foo.rs", Some((42, 7)))]),
+            (&["+42", "foo.rs"], vec![("foo.rs", Some((42, 1)))]),
+            (
+                &["+42:7", "foo.rs", "bar.rs"],
+                vec![("foo.rs", Some((42, 7))), ("bar.rs", None)],
+            ),
+            (&["foo.rs:x"], vec![("foo.rs:x", None)]),
+            (&["foo.rs:1:2:3"], vec![("foo.rs:1", Some((2, 3)))]),
+            (&[":42"], vec![(":42", None)]),
+            (&[":42:7"], vec![(":42", Some((7, 1)))]),
+            (&["a:b:3"], vec![("a:b", Some((3, 1)))]),
+        ] {
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(path, pos)| (path.to_string(), pos))
+                .collect();
+            assert_eq!(parse_file_args(args(input)), Ok(expected), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parse_file_args_errors() {
+        for (input, expected) in [
+            (&["foo.rs", "+42"][..], "No file is specified for +42"),
+            (&["+x", "foo.rs"], "Invalid position '+x'"),
+            (&["+1:2:3", "foo.rs"], "Invalid position '+1:2:3'"),
+        ] {
+            assert_eq!(parse_file_args(args(input)), Err(expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn parse_existing_file_containing_colon() {
+        let dir = env::temp_dir().join(format!("kiro-test-file-args-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("foo:42").to_str().unwrap().to_string();
+        fs::write(&path, "").unwrap();
+        let parsed = parse_file_args(vec![path.clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(parsed, Ok(vec![(path, None)]));
+    }
+}
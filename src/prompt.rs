@@ -96,8 +96,7 @@ impl TextSearch {
                 .unwrap_or(0),
             FindDir::Back => self.text[..self.current_offset]
                 .char_indices()
-                .rev()
-                .next()
+                .next_back()
                 .map(|(idx, _)| idx)
                 .unwrap_or_else(|| self.text.len()),
         };
@@ -167,7 +166,7 @@ impl TextSearch {
 
         // Set all match highlights in screen
         let matches =
-            self.calculate_matches(input, current_match, &prompt.screen, prompt.buf.rows());
+            self.calculate_matches(input, current_match, prompt.screen, prompt.buf.rows());
         prompt.hl.set_matches(matches);
 
         // Update highlights since matched word was updated
@@ -298,7 +297,8 @@ impl Action for TextSearch {
             }
             Input(_) => {
                 prompt.screen.set_info_message("Not found");
-                result
+                // Not found is reported as canceled without restoring the cursor position
+                return Ok(Canceled);
             }
         };
 
@@ -371,8 +371,8 @@ impl<'a, W: Write> Prompt<'a, W> {
 
     fn render_screen(&mut self, input: &str, template: &PromptTemplate<'_>) -> Result<()> {
         self.screen.set_info_message(template.build(input));
-        self.sb.update_from_buf(&self.buf);
-        self.screen.render(self.buf, &mut self.hl, &self.sb)?;
+        self.sb.update_from_buf(self.buf);
+        self.screen.render(self.buf, self.hl, self.sb)?;
 
         let row = self.screen.rows() + 2;
         let col = template.cursor_col(input);
@@ -393,9 +393,8 @@ impl<'a, W: Write> Prompt<'a, W> {
         let mut canceled = false;
 
         let template = {
-            let mut it = prompt.as_ref().splitn(2, "{}");
-            let prefix = it.next().unwrap();
-            let suffix = it.next().unwrap();
+            let (prefix, suffix) = prompt.as_ref().split_once("{}").unwrap();
+
             PromptTemplate::new(prefix, suffix)
         };
 
@@ -440,6 +439,7 @@ impl<'a, W: Write> Prompt<'a, W> {
                 }
                 (Key(b), false) => buf.push(*b as char),
                 (Utf8Key(c), false) => buf.push(*c),
+                (Paste(text), ..) => buf.extend(text.chars().filter(|c| !c.is_control())),
                 _ => {}
             }
 
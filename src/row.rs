@@ -1,16 +1,51 @@
 use crate::error::{Error, Result};
+use std::cmp;
+use std::iter;
 use std::ops;
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthChar;
 
-const TAB_STOP: usize = 8;
+pub const DEFAULT_TAB_STOP: usize = 8;
+
+// Marker in `Row::widths` for characters which are not at start of grapheme cluster
+const CONTINUATION: u8 = u8::MAX;
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+// Display width of an extended grapheme cluster. Terminals render emoji sequences such as ZWJ
+// sequences, flags and emoji with presentation selector as one double-width character.
+fn cluster_width(cluster: &str) -> usize {
+    let mut chars = cluster.chars();
+    let first = chars.next().unwrap_or(' ');
+    let width = first.width_cjk().unwrap_or(0);
+    if chars.next().is_none() {
+        width
+    } else if is_regional_indicator(first) || cluster.contains(&['\u{200D}', '\u{FE0F}'][..]) {
+        2
+    } else {
+        width
+    }
+}
+
+// Display width of text (without tabs) counted per grapheme cluster
+pub fn display_width(s: &str) -> usize {
+    s.graphemes(true).map(cluster_width).sum()
+}
 
-#[derive(Default)]
 pub struct Row {
     buf: String,
     render: String,
     // Cache of byte indices of characters in `buf`. This will be empty when `buf` only contains
     // single byte characters not to allocate memory.
     indices: Vec<usize>,
+    // Display width of each character in `buf`. The first character of grapheme cluster has width
+    // of the whole cluster and the rest are CONTINUATION. This will be empty when every grapheme
+    // cluster consists of one character.
+    widths: Vec<u8>,
+    // Width of tab stop to render tab characters
+    tab_stop: usize,
 }
 
 impl Row {
@@ -19,19 +54,44 @@ impl Row {
             buf: "".to_string(),
             render: "".to_string(),
             indices: Vec::with_capacity(0),
+            widths: Vec::with_capacity(0),
+            tab_stop: DEFAULT_TAB_STOP,
         }
     }
 
     pub fn new<S: Into<String>>(line: S) -> Result<Row> {
+        Self::with_tab_stop(line, DEFAULT_TAB_STOP)
+    }
+
+    pub fn with_tab_stop<S: Into<String>>(line: S, tab_stop: usize) -> Result<Row> {
         let mut row = Row {
             buf: line.into(),
             render: "".to_string(),
             indices: Vec::with_capacity(0),
+            widths: Vec::with_capacity(0),
+            tab_stop,
         };
         row.update_render()?;
         Ok(row)
     }
 
+    pub fn tab_stop(&self) -> usize {
+        self.tab_stop
+    }
+
+    // Returns true when render text was changed
+    pub fn set_tab_stop(&mut self, tab_stop: usize) -> bool {
+        if self.tab_stop == tab_stop {
+            return false;
+        }
+        self.tab_stop = tab_stop;
+        if !self.buf.contains('\t') {
+            return false; // Rendering does not depend on tab stop
+        }
+        self.update_render().unwrap();
+        true
+    }
+
     // Returns number of characters
     pub fn len(&self) -> usize {
         if self.indices.is_empty() {
@@ -84,26 +144,41 @@ impl Row {
     fn update_render(&mut self) -> Result<()> {
         self.render.clear();
         self.render.reserve(self.buf.len());
+        self.widths.clear();
         let mut index = 0;
         let mut num_chars = 0;
-
-        for c in self.buf.chars() {
-            if c == '\t' {
-                loop {
-                    self.render.push(' ');
-                    index += 1;
-                    if index % TAB_STOP == 0 {
-                        break;
+        let mut num_clusters = 0;
+
+        for cluster in self.buf.graphemes(true) {
+            let width = cluster_width(cluster);
+            for (i, c) in cluster.chars().enumerate() {
+                if c == '\t' {
+                    loop {
+                        self.render.push(' ');
+                        index += 1;
+                        if index % self.tab_stop == 0 {
+                            break;
+                        }
                     }
+                } else if c.width_cjk().is_some() {
+                    self.render.push(c);
+                } else {
+                    // Control sequences are valid for UTF-8 but they should not appear in text.
+                    return Err(Error::ControlCharInText(c));
                 }
-            } else if let Some(width) = c.width_cjk() {
+                self.widths
+                    .push(if i == 0 { width as u8 } else { CONTINUATION });
+                num_chars += 1;
+            }
+            if cluster != "\t" {
                 index += width;
-                self.render.push(c);
-            } else {
-                // Control sequences are valid for UTF-8 but they should not appear in text.
-                return Err(Error::ControlCharInText(c));
             }
-            num_chars += 1;
+            num_clusters += 1;
+        }
+
+        if num_clusters == num_chars {
+            // Width of each character can be calculated from the character itself
+            self.widths = Vec::with_capacity(0);
         }
 
         if num_chars == self.buf.len() {
@@ -121,17 +196,153 @@ impl Row {
         Ok(())
     }
 
+    // Display width of the character at `cx` except for tab. Characters following the first
+    // character of grapheme cluster have zero width
+    fn width_at(&self, cx: usize, ch: char) -> usize {
+        match self.widths.get(cx) {
+            Some(&CONTINUATION) => 0,
+            Some(&w) => w as usize,
+            None => ch.width_cjk().unwrap(),
+        }
+    }
+
+    // Returns whether the character index is at start of grapheme cluster (or at end of line)
+    pub fn is_boundary(&self, cx: usize) -> bool {
+        self.widths.get(cx) != Some(&CONTINUATION)
+    }
+
+    // Index of the start of grapheme cluster before `cx`
+    pub fn prev_boundary(&self, cx: usize) -> usize {
+        let mut x = cx.saturating_sub(1);
+        while x > 0 && !self.is_boundary(x) {
+            x -= 1;
+        }
+        x
+    }
+
+    // Index of the start of grapheme cluster after `cx`. At the last cluster, it returns length
+    // of the row
+    pub fn next_boundary(&self, cx: usize) -> usize {
+        let len = self.len();
+        let mut x = cx + 1;
+        while x < len && !self.is_boundary(x) {
+            x += 1;
+        }
+        cmp::min(x, len)
+    }
+
+    // Display width of each character in render text
+    pub fn render_widths(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut rx = 0;
+        self.buf.chars().enumerate().flat_map(move |(cx, ch)| {
+            if ch == '\t' {
+                let spaces = self.tab_stop - (rx % self.tab_stop);
+                rx += spaces;
+                iter::repeat(1).take(spaces)
+            } else {
+                let width = self.width_at(cx, ch);
+                rx += width;
+                iter::repeat(width).take(1)
+            }
+        })
+    }
+
+    // Indices in render text where each screen line starts when the text is wrapped at `width`
+    // columns. Lines are broken after spaces when possible. When the last line fills the width, an
+    // empty line follows so that the cursor at end of line can be put on screen
+    pub fn wrap_starts(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let mut col = 0;
+        let mut after_space = None; // Index and column after the last space in current line
+        for (i, (c, w)) in self.render.chars().zip(self.render_widths()).enumerate() {
+            if col + w > width && col > 0 {
+                let (start, start_col) = after_space.unwrap_or((i, col));
+                starts.push(start);
+                col -= start_col;
+                after_space = None;
+            }
+            col += w;
+            if c == ' ' {
+                after_space = Some((i + 1, col));
+            }
+        }
+        if col >= width {
+            starts.push(self.render.chars().count());
+        }
+        starts
+    }
+
     pub fn rx_from_cx(&self, cx: usize) -> usize {
-        self[..cx].chars().fold(0, |rx, ch| {
+        self[..cx].chars().enumerate().fold(0, |rx, (x, ch)| {
             if ch == '\t' {
-                // Proceed TAB_STOP spaces then subtract spaces by mod TAB_STOP
-                rx + TAB_STOP - (rx % TAB_STOP)
+                // Proceed tab stop spaces then subtract spaces by mod tab stop
+                rx + self.tab_stop - (rx % self.tab_stop)
             } else {
-                rx + ch.width_cjk().unwrap()
+                rx + self.width_at(x, ch)
             }
         })
     }
 
+    // Index of the first character displayed at or after the display column `rx`. A character
+    // which starts before the column is not counted even if its width reaches the column
+    pub fn cx_from_rx(&self, rx: usize) -> usize {
+        let mut current = 0;
+        for (cx, ch) in self.buf.chars().enumerate() {
+            if current >= rx && self.is_boundary(cx) {
+                return cx;
+            }
+            if ch == '\t' {
+                current += self.tab_stop - (current % self.tab_stop);
+            } else {
+                current += self.width_at(cx, ch);
+            }
+        }
+        self.len()
+    }
+
+    // Index of character in render text which corresponds to the character at `cx` in buffer
+    pub fn render_idx_from_cx(&self, cx: usize) -> usize {
+        let mut rx = 0;
+        let mut idx = 0;
+        for (x, ch) in self[..cx].chars().enumerate() {
+            if ch == '\t' {
+                let spaces = self.tab_stop - (rx % self.tab_stop);
+                rx += spaces;
+                idx += spaces;
+            } else {
+                rx += self.width_at(x, ch);
+                idx += 1;
+            }
+        }
+        idx
+    }
+
+    // Index of character in buffer which corresponds to the character at `idx` in render text. When
+    // the index points to spaces of a tab or to the middle of grapheme cluster, it returns index of
+    // the tab or the start of the cluster
+    pub fn cx_from_render_idx(&self, idx: usize) -> usize {
+        let mut rx = 0;
+        let mut i = 0;
+        let mut start = 0;
+        for (cx, ch) in self.buf.chars().enumerate() {
+            if self.is_boundary(cx) {
+                start = cx;
+            }
+            if ch == '\t' {
+                let spaces = self.tab_stop - (rx % self.tab_stop);
+                rx += spaces;
+                i += spaces;
+            } else {
+                rx += self.width_at(cx, ch);
+                i += 1;
+            }
+            if idx < i {
+                return start;
+            }
+        }
+        self.len()
+    }
+
     pub fn insert_char(&mut self, at: usize, c: char) {
         if self.len() <= at {
             self.buf.push(c);
@@ -1,15 +1,15 @@
 use crate::error::{Error, Result};
-use crate::highlight::Highlighting;
+use crate::highlight::{Highlight, Highlighting};
 use crate::input::{InputSeq, KeySeq};
 use crate::row::Row;
 use crate::signal::SigwinchWatcher;
 use crate::status_bar::StatusBar;
 use crate::term_color::{Color, TermColor};
-use crate::text_buffer::TextBuffer;
+use crate::text_buffer::{CursorDir, TextBuffer};
 use std::cmp;
 use std::io::Write;
+use std::ops;
 use std::time::SystemTime;
-use unicode_width::UnicodeWidthChar;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const HELP: &str = "\
@@ -32,11 +32,40 @@ pub const HELP: &str = "\
     Alt-P or Ctrl-UP              : Move cursor to previous paragraph
     Alt-<                         : Move cursor to top of file
     Alt->                         : Move cursor to bottom of file
+    Alt-M                         : Move cursor to matching bracket
+    Alt-G                         : Go to line (N, N:COL, +N, -N or N%)
     Ctrl-H or BACKSPACE           : Delete character
     Ctrl-D or DELETE              : Delete next character
     Ctrl-W                        : Delete a word
     Ctrl-J                        : Delete until head of line
     Ctrl-K                        : Delete until end of line
+    Ctrl-SPACE                    : Set or unset mark to select region
+    Alt-I                         : Reindent current line or region
+    Alt-Shift-I                   : Set indentation and tab stop of buffer (e.g. tab:4, 2)
+    Alt-;                         : Toggle comment of current line or region
+    Alt-J                         : Add cursor at next occurrence of word
+    Alt-A                         : Add cursor on each line in region
+    Alt-O                         : Remove secondary cursors
+    Alt-R {K,Y,I,T}               : Kill, yank, insert text or replace rectangle
+    Ctrl-T                        : Transpose characters
+    Alt-T                         : Transpose words
+    Alt-UP or Alt-DOWN            : Move current line or region up or down
+    Alt-Y                         : Duplicate current line or region
+    Alt-U / Alt-L / Alt-C         : Upcase, downcase or capitalize word or region
+    Alt-Q / Alt-Shift-Q           : Fill paragraph to fill column or join it into one line
+    Alt-S {S,N,I,U,R}             : Sort (Shift to reverse), uniq or reverse lines in region
+    Alt-|                         : Filter region (or buffer) through shell command
+    Alt-!                         : Insert output of shell command at cursor
+    Alt-D / Alt-BACKSPACE         : Delete word forward or backward (joining lines at edge)
+    Alt-K                         : Delete current line
+    Alt-W                         : Toggle soft wrap of long lines
+    Alt-Z {C}                     : Delete until next occurrence of character C
+    INSERT                        : Toggle overwrite mode
+    Alt-( / Alt-)                 : Start or stop recording keyboard macro
+    Alt-E                         : Replay keyboard macro
+    Alt-Shift-E                   : Replay keyboard macro N times or until search fails
+    Alt-Shift-S / Alt-Shift-L     : Save or load keyboard macro
+    Alt-0 ... Alt-9               : Repeat next command N times (e.g. Alt-2 Alt-0 DOWN)
     Ctrl-U                        : Undo last change
     Ctrl-R                        : Redo last undo change
     Ctrl-G                        : Search text
@@ -91,6 +120,17 @@ where
     Err(Error::UnknownWindowSize) // Give up
 }
 
+// Screen line in the wrapped row and column in the screen line of the index in render text
+fn wrapped_position(row: &Row, starts: &[usize], idx: usize) -> (usize, usize) {
+    let line = starts.iter().rposition(|&s| s <= idx).unwrap_or(0);
+    let col = row
+        .render_widths()
+        .skip(starts[line])
+        .take(idx - starts[line])
+        .sum();
+    (line, col)
+}
+
 fn too_small_window(width: usize, height: usize) -> bool {
     width < 1 || height < 3
 }
@@ -139,6 +179,16 @@ pub struct Screen<W: Write> {
     // Watch resize signal
     sigwinch: SigwinchWatcher,
     term_color: TermColor,
+    // True while the cursor shape is changed for overwrite mode
+    overwrite_cursor: bool,
+    // When soft wrap is enabled, long rows are wrapped into multiple screen lines instead of
+    // scrolling horizontally
+    soft_wrap: bool,
+    // Row and its screen line at top of screen in soft wrap mode. The line is ignored when the
+    // row is not `rowoff`
+    wrapoff: (usize, usize),
+    // Y coordinate of cursor on screen
+    cursor_row: usize,
     pub cursor_moved: bool,
     pub rowoff: usize, // Row scroll offset
     pub coloff: usize, // Column scroll offset
@@ -162,6 +212,9 @@ impl<W: Write> Screen<W> {
         // Enter alternate screen buffer to restore previous screen on quit
         // https://www.xfree86.org/current/ctlseqs.html#The%20Alternate%20Screen%20Buffer
         output.write(b"\x1b[?47h")?;
+        // Enable bracketed paste mode to receive pasted text at once
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Bracketed-Paste-Mode
+        output.write(b"\x1b[?2004h")?;
 
         Ok(Self {
             output,
@@ -177,6 +230,10 @@ impl<W: Write> Screen<W> {
             dirty_start: Some(0), // Render entire screen at first paint
             sigwinch: SigwinchWatcher::new()?,
             term_color: TermColor::from_env(),
+            overwrite_cursor: false,
+            soft_wrap: false,
+            wrapoff: (0, 0),
+            cursor_row: 0,
             cursor_moved: true,
             rowoff: 0,
             coloff: 0,
@@ -189,6 +246,20 @@ impl<W: Write> Screen<W> {
         Ok(())
     }
 
+    // Change cursor shape to underline in overwrite mode with DECSCUSR sequence. Terminals which
+    // don't support the sequence simply ignore it. https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
+    pub fn set_overwrite_cursor(&mut self, overwrite: bool) -> Result<()> {
+        if self.overwrite_cursor == overwrite {
+            return Ok(());
+        }
+        self.overwrite_cursor = overwrite;
+        if overwrite {
+            self.write_flush(b"\x1b[4 q")
+        } else {
+            self.write_flush(b"\x1b[0 q")
+        }
+    }
+
     fn trim_line<S: AsRef<str>>(&self, line: &S) -> String {
         let line = line.as_ref();
         if line.len() <= self.coloff {
@@ -295,13 +366,69 @@ impl<W: Write> Screen<W> {
         Ok(())
     }
 
+    // Draw characters in the range of render text. Characters at secondary cursors are inverted.
+    // `at_end` is true when the range reaches end of line
+    fn draw_render_text<B: Write>(
+        &self,
+        mut buf: B,
+        row: &Row,
+        hl_line: &[Highlight],
+        cursor_indices: &[usize],
+        range: ops::Range<usize>,
+        at_end: bool,
+    ) -> Result<()> {
+        let mut prev_color = Color::Reset;
+        for (i, (c, hl)) in row
+            .render_text()
+            .chars()
+            .zip(hl_line.iter())
+            .enumerate()
+            .skip(range.start)
+            .take(range.len())
+        {
+            if cursor_indices.contains(&i) {
+                buf.write(self.term_color.sequence(Color::Reset))?;
+                buf.write(self.term_color.sequence(Color::Invert))?;
+                write!(buf, "{}", c)?;
+                buf.write(self.term_color.sequence(Color::Reset))?;
+                prev_color = Color::Reset;
+                continue;
+            }
+
+            let color = hl.color();
+            if color != prev_color {
+                if prev_color.has_bg_color() {
+                    buf.write(self.term_color.sequence(Color::Reset))?;
+                }
+                buf.write(self.term_color.sequence(color))?;
+                prev_color = color;
+            }
+
+            write!(buf, "{}", c)?;
+        }
+
+        if at_end && cursor_indices.contains(&range.end) {
+            // Cursor at end of line
+            buf.write(self.term_color.sequence(Color::Reset))?;
+            buf.write(self.term_color.sequence(Color::Invert))?;
+            buf.write(b" ")?;
+        }
+
+        Ok(())
+    }
+
     fn draw_rows<B: Write>(
         &self,
         mut buf: B,
         dirty_start: usize,
         rows: &[Row],
+        cursors: &[(usize, usize)],
         hl: &Highlighting,
     ) -> Result<()> {
+        if self.soft_wrap {
+            return self.draw_wrapped_rows(buf, dirty_start, rows, cursors, hl);
+        }
+
         let row_len = rows.len();
 
         buf.write(self.term_color.sequence(Color::Reset))?;
@@ -322,27 +449,39 @@ impl<W: Write> Screen<W> {
             } else {
                 let row = &rows[file_row];
 
+                // Secondary cursors are rendered as inverted characters
+                let cursor_indices: Vec<_> = cursors
+                    .iter()
+                    .filter(|&&(_, y)| y == file_row)
+                    .map(|&(x, _)| row.render_idx_from_cx(x))
+                    .collect();
+
+                // Find range of characters in screen considering column offset
+                let len = row.render_text().chars().count();
                 let mut col = 0;
-                let mut prev_color = Color::Reset;
-                for (c, hl) in row.render_text().chars().zip(hl.lines[file_row].iter()) {
-                    col += c.width_cjk().unwrap_or(1);
+                let mut start = None;
+                let mut end = len;
+                for (i, width) in row.render_widths().enumerate() {
+                    col += width;
                     if col <= self.coloff {
                         continue;
                     } else if col > self.num_cols + self.coloff {
+                        end = i;
                         break;
                     }
-
-                    let color = hl.color();
-                    if color != prev_color {
-                        if prev_color.has_bg_color() {
-                            buf.write(self.term_color.sequence(Color::Reset))?;
-                        }
-                        buf.write(self.term_color.sequence(color))?;
-                        prev_color = color;
-                    }
-
-                    write!(buf, "{}", c)?;
+                    start.get_or_insert(i);
                 }
+                let start = start.unwrap_or(end);
+                let at_end = end == len && col >= self.coloff && col < self.num_cols + self.coloff;
+
+                self.draw_render_text(
+                    &mut buf,
+                    row,
+                    &hl.lines[file_row],
+                    &cursor_indices,
+                    start..end,
+                    at_end,
+                )?;
             }
 
             // Ensure to end with reset color sequence. Otherwise, when background color is highlighted
@@ -356,13 +495,78 @@ impl<W: Write> Screen<W> {
         Ok(())
     }
 
+    fn draw_wrapped_rows<B: Write>(
+        &self,
+        mut buf: B,
+        dirty_start: usize,
+        rows: &[Row],
+        cursors: &[(usize, usize)],
+        hl: &Highlighting,
+    ) -> Result<()> {
+        buf.write(self.term_color.sequence(Color::Reset))?;
+
+        let mut y = 0;
+        let mut file_row = self.rowoff;
+        let mut first_line = self.wrap_line();
+        while y < self.rows() {
+            let row = if let Some(row) = rows.get(file_row) {
+                row
+            } else {
+                if file_row >= dirty_start {
+                    write!(buf, "\x1b[{}H", y + 1)?;
+                    buf.write(self.term_color.sequence(Color::NonText))?;
+                    buf.write(b"~")?;
+                    buf.write(self.term_color.sequence(Color::Reset))?;
+                    buf.write(b"\x1b[K")?;
+                }
+                y += 1;
+                file_row += 1;
+                continue;
+            };
+
+            let cursor_indices: Vec<_> = cursors
+                .iter()
+                .filter(|&&(_, y)| y == file_row)
+                .map(|&(x, _)| row.render_idx_from_cx(x))
+                .collect();
+            let starts = row.wrap_starts(self.num_cols);
+            let len = row.render_text().chars().count();
+
+            for (i, &start) in starts.iter().enumerate().skip(first_line) {
+                if y >= self.rows() {
+                    break;
+                }
+                if file_row >= dirty_start {
+                    write!(buf, "\x1b[{}H", y + 1)?;
+                    let end = starts.get(i + 1).copied().unwrap_or(len);
+                    self.draw_render_text(
+                        &mut buf,
+                        row,
+                        &hl.lines[file_row],
+                        &cursor_indices,
+                        start..end,
+                        i + 1 == starts.len(),
+                    )?;
+                    buf.write(self.term_color.sequence(Color::Reset))?;
+                    buf.write(b"\x1b[K")?;
+                }
+                y += 1;
+            }
+
+            first_line = 0;
+            file_row += 1;
+        }
+
+        Ok(())
+    }
+
     fn redraw(
         &mut self,
         text_buf: &TextBuffer,
         hl: &Highlighting,
         status_bar: &StatusBar,
     ) -> Result<()> {
-        let cursor_row = text_buf.cy() - self.rowoff + 1;
+        let cursor_row = self.cursor_row + 1;
         let cursor_col = self.rx - self.coloff + 1;
         let draw_message = self.draw_message;
 
@@ -384,7 +588,13 @@ impl<W: Write> Screen<W> {
 
         let mut buf = Vec::with_capacity((self.rows() + 2) * self.num_cols);
         if let Some(s) = self.dirty_start {
-            self.draw_rows(&mut buf, s, text_buf.rows(), hl)?;
+            self.draw_rows(
+                &mut buf,
+                s,
+                text_buf.rows(),
+                text_buf.secondary_cursors(),
+                hl,
+            )?;
         }
 
         // When message bar opens/closes, position of status bar is changed
@@ -415,8 +625,8 @@ impl<W: Write> Screen<W> {
 
     fn next_coloff(&self, want_stop: usize, row: &Row) -> usize {
         let mut coloff = 0;
-        for c in row.render_text().chars() {
-            coloff += c.width_cjk().unwrap_or(1);
+        for width in row.render_widths() {
+            coloff += width;
             if coloff >= want_stop {
                 // Screen cannot start from at the middle of double-width character
                 break;
@@ -425,7 +635,90 @@ impl<W: Write> Screen<W> {
         coloff
     }
 
+    // Screen line of `rowoff` row at top of screen in soft wrap mode
+    fn wrap_line(&self) -> usize {
+        match self.wrapoff {
+            (row, line) if row == self.rowoff => line,
+            _ => 0,
+        }
+    }
+
+    fn wrapped_lines(&self, rows: &[Row], y: usize) -> usize {
+        rows.get(y)
+            .map(|r| r.wrap_starts(self.num_cols).len())
+            .unwrap_or(1)
+    }
+
+    // Screen line and column of the cursor in soft wrap mode
+    fn wrapped_cursor(&self, rows: &[Row], (cx, cy): (usize, usize)) -> (usize, usize) {
+        match rows.get(cy) {
+            Some(row) => {
+                let starts = row.wrap_starts(self.num_cols);
+                wrapped_position(row, &starts, row.render_idx_from_cx(cx))
+            }
+            None => (0, 0),
+        }
+    }
+
+    // Number of screen lines from `top` to `bottom` in soft wrap mode. Each of them is a pair of
+    // row and screen line in the row
+    fn screen_lines_between(
+        &self,
+        rows: &[Row],
+        top: (usize, usize),
+        bottom: (usize, usize),
+    ) -> usize {
+        if top.0 == bottom.0 {
+            return bottom.1.saturating_sub(top.1);
+        }
+        let middle: usize = (top.0 + 1..bottom.0)
+            .map(|y| self.wrapped_lines(rows, y))
+            .sum();
+        self.wrapped_lines(rows, top.0) - top.1 + middle + bottom.1
+    }
+
+    fn do_scroll_wrapped(&mut self, rows: &[Row], (cx, cy): (usize, usize)) {
+        let prev_top = (self.rowoff, self.wrap_line());
+        let (line, col) = self.wrapped_cursor(rows, (cx, cy));
+        self.rx = col;
+        self.coloff = 0;
+
+        let mut top = (
+            self.rowoff,
+            cmp::min(prev_top.1, self.wrapped_lines(rows, self.rowoff) - 1),
+        );
+        if (cy, line) < top {
+            // Scroll up when cursor is above the top of window
+            top = (cy, line);
+        }
+        if cy >= top.0 + self.rows() {
+            // Each row has one screen line at least
+            top = (cy + 1 - self.rows(), 0);
+        }
+        // Scroll down until the cursor is in screen
+        let mut lines = self.screen_lines_between(rows, top, (cy, line));
+        while lines >= self.rows() {
+            if top.1 + 1 < self.wrapped_lines(rows, top.0) {
+                top.1 += 1;
+            } else {
+                top = (top.0 + 1, 0);
+            }
+            lines -= 1;
+        }
+
+        self.rowoff = top.0;
+        self.wrapoff = top;
+        self.cursor_row = lines;
+        if prev_top != top {
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
     fn do_scroll(&mut self, rows: &[Row], (cx, cy): (usize, usize)) {
+        if self.soft_wrap {
+            return self.do_scroll_wrapped(rows, (cx, cy));
+        }
+
         let prev_rowoff = self.rowoff;
         let prev_coloff = self.coloff;
 
@@ -452,6 +745,8 @@ impl<W: Write> Screen<W> {
             self.coloff = self.next_coloff(self.rx - self.num_cols + 1, &rows[cy]);
         }
 
+        self.cursor_row = cy - self.rowoff;
+
         if prev_rowoff != self.rowoff || prev_coloff != self.coloff {
             // If scroll happens, all rows on screen must be updated
             // TODO: Improve rendering on scrolling up/down using scroll region commands \x1b[M/\x1b[D.
@@ -489,6 +784,12 @@ impl<W: Write> Screen<W> {
         self.do_scroll(buf.rows(), buf.cursor());
         self.update_message_bar()?; // This must be updated here since it affects area of highlighting
         hl.update(buf.rows(), self.rowoff + self.rows());
+        // Matching bracket is searched only in the screen
+        let lines = self.rowoff..self.rowoff + self.rows();
+        if let Some(line) = hl.update_bracket_match(buf.rows(), buf.cursor(), lines) {
+            self.set_dirty_start(line);
+            hl.update(buf.rows(), self.rowoff + self.rows());
+        }
         self.redraw(buf, hl, status_bar)?;
         self.after_render();
         Ok(())
@@ -550,6 +851,61 @@ impl<W: Write> Screen<W> {
         self.write_flush(&buf)
     }
 
+    // Returns true when soft wrap is enabled
+    pub fn toggle_soft_wrap(&mut self) -> bool {
+        self.soft_wrap = !self.soft_wrap;
+        self.coloff = 0;
+        self.wrapoff = (self.rowoff, 0);
+        self.set_dirty_start(self.rowoff);
+        self.soft_wrap
+    }
+
+    pub fn soft_wrap(&self) -> bool {
+        self.soft_wrap
+    }
+
+    // Cursor position after moving up or down by one screen line in soft wrap mode. The cursor
+    // keeps its column in screen line as much as possible
+    pub fn move_cursor_wrapped(
+        &self,
+        rows: &[Row],
+        (cx, cy): (usize, usize),
+        dir: CursorDir,
+    ) -> (usize, usize) {
+        let (line, col) = self.wrapped_cursor(rows, (cx, cy));
+        let (y, line) = match dir {
+            CursorDir::Up if line > 0 => (cy, line - 1),
+            CursorDir::Up if cy > 0 => (cy - 1, self.wrapped_lines(rows, cy - 1) - 1),
+            CursorDir::Down if cy < rows.len() && line + 1 < self.wrapped_lines(rows, cy) => {
+                (cy, line + 1)
+            }
+            CursorDir::Down if cy < rows.len() => (cy + 1, 0),
+            _ => return (cx, cy),
+        };
+
+        let row = match rows.get(y) {
+            Some(row) => row,
+            None => return (0, y),
+        };
+        let starts = row.wrap_starts(self.num_cols);
+        let start = starts[line];
+        // Cursor cannot be put after the last character of screen line except for the last line
+        let last = match starts.get(line + 1) {
+            Some(&next) => cmp::max(next.saturating_sub(1), start),
+            None => row.render_text().chars().count(),
+        };
+        let mut idx = start;
+        let mut c = 0;
+        for width in row.render_widths().skip(start) {
+            if idx >= last || c + width > col {
+                break;
+            }
+            c += width;
+            idx += 1;
+        }
+        (row.cx_from_render_idx(idx), y)
+    }
+
     pub fn set_dirty_start(&mut self, start: usize) {
         if let Some(s) = self.dirty_start {
             if s < start {
@@ -632,7 +988,7 @@ impl<W: Write> Drop for Screen<W> {
         // Back to normal screen buffer from alternate screen buffer
         // https://www.xfree86.org/current/ctlseqs.html#The%20Alternate%20Screen%20Buffer
         // Note that we used \x1b[2J\x1b[H previously but it did not erase screen.
-        self.write_flush(b"\x1b[?47l\x1b[H")
+        self.write_flush(b"\x1b[0 q\x1b[?2004l\x1b[?47l\x1b[H")
             .expect("Back to normal screen buffer");
     }
 }
@@ -0,0 +1,186 @@
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+
+// Commands are run with 'sh -c' since $SHELL may not be a POSIX compatible shell
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+fn first_line(text: &str) -> Option<&str> {
+    text.lines().map(str::trim).find(|l| !l.is_empty())
+}
+
+fn failure_message(command: &str, status: ExitStatus, stderr: &str) -> String {
+    let status = match status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => "killed by signal".to_string(),
+    };
+    match first_line(stderr) {
+        Some(line) => format!("'{}' failed with {}: {}", command, status, line),
+        None => format!("'{}' failed with {}", command, status),
+    }
+}
+
+// Output of command must be text which can be inserted in text buffer
+fn output_text(command: &str, stdout: Vec<u8>) -> Result<String, String> {
+    let stdout = String::from_utf8(stdout)
+        .map_err(|_| format!("Output of '{}' is not valid UTF-8", command))?
+        .replace("\r\n", "\n");
+    if let Some(c) = stdout
+        .chars()
+        .find(|&c| c.is_control() && c != '\t' && c != '\n')
+    {
+        return Err(format!(
+            "Output of '{}' contains control character {:?}",
+            command, c
+        ));
+    }
+    Ok(stdout)
+}
+
+// Stdout and the first line of stderr of the command which succeeded, or an error message which
+// contains its stderr
+pub type Output = Result<(String, Option<String>), String>;
+
+fn read_all<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+// Command running in background. Its outputs are read in other threads so that the editor can
+// keep handling keys and redrawing screen while waiting for the command
+pub struct Running {
+    command: String,
+    child: Child,
+    stdin: Option<JoinHandle<io::Result<()>>>,
+    stdout: JoinHandle<Vec<u8>>,
+    stderr: JoinHandle<Vec<u8>>,
+}
+
+impl Running {
+    pub fn spawn(command: &str) -> Result<Self, String> {
+        Self::start(command, None)
+    }
+
+    // Run the command passing `input` to its stdin
+    pub fn spawn_with_input(command: &str, input: String) -> Result<Self, String> {
+        Self::start(command, Some(input))
+    }
+
+    fn start(command: &str, input: Option<String>) -> Result<Self, String> {
+        let stdin = if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        let mut child = shell_command(command)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Run in its own process group so that kill() can stop processes spawned by 'sh -c'
+            .process_group(0)
+            .spawn()
+            .map_err(|e| format!("Could not run '{}': {}", command, e))?;
+        // Write input in another thread not to be blocked when pipe of stdin is full
+        let stdin = input.map(|input| {
+            let mut stdin = child.stdin.take().unwrap();
+            thread::spawn(move || stdin.write_all(input.as_bytes()))
+        });
+        let stdout = read_all(child.stdout.take().unwrap());
+        let stderr = read_all(child.stderr.take().unwrap());
+        Ok(Self {
+            command: command.to_string(),
+            child,
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+
+    pub fn is_finished(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+
+    // Wait for the command to exit and collect its output
+    pub fn finish(mut self) -> Output {
+        let command = self.command;
+        let status = self
+            .child
+            .wait()
+            .map_err(|e| format!("Could not wait for '{}': {}", command, e))?;
+        if let Some(stdin) = self.stdin {
+            // Writing input may fail with broken pipe when the command exits without reading all
+            // of it
+            let _ = stdin.join();
+        }
+        let stdout = self.stdout.join().unwrap_or_default();
+        let stderr = self.stderr.join().unwrap_or_default();
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !status.success() {
+            return Err(failure_message(&command, status, &stderr));
+        }
+        let stdout = output_text(&command, stdout)?;
+        Ok((stdout, first_line(&stderr).map(str::to_string)))
+    }
+
+    // Kill all processes in the process group of the command. Killing only 'sh -c' would leave its
+    // child processes running and writing to stdout
+    pub fn kill(mut self) {
+        // Process group ID is the same as PID of 'sh' since it was spawned with process_group(0)
+        unsafe {
+            libc::killpg(self.child.id() as libc::pid_t, libc::SIGKILL);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    fn is_alive(pid: &str) -> bool {
+        // Killed process may remain as zombie until it is reaped by its new parent
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => !stat.contains(") Z "),
+            Err(_) => false,
+        }
+    }
+
+    #[test]
+    fn kill_child_processes_of_command() {
+        let path = std::env::temp_dir().join(format!("kiro-shell-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let command = format!("sleep 30 & echo $! > {}; wait", path.display());
+        let running = Running::spawn(&command).unwrap();
+
+        let start = Instant::now();
+        let pid = loop {
+            match fs::read_to_string(&path) {
+                Ok(s) if s.ends_with('\n') => break s.trim().to_string(),
+                _ if start.elapsed() > Duration::from_secs(10) => panic!("sleep did not start"),
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        fs::remove_file(&path).unwrap();
+        assert!(is_alive(&pid));
+
+        running.kill();
+        let start = Instant::now();
+        while is_alive(&pid) {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "'sleep' is still running"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::language::Language;
+use crate::language::{Indent, Language};
 use crate::text_buffer::TextBuffer;
 
 pub struct StatusBar {
@@ -7,6 +7,9 @@ pub struct StatusBar {
     pub lang: Language,
     pub buf_pos: (usize, usize),
     pub line_pos: (usize, usize),
+    pub overwrite: bool,
+    pub recording: bool,
+    pub indent: (Indent, usize), // Indentation and tab stop
     pub redraw: bool,
 }
 
@@ -35,6 +38,9 @@ impl StatusBar {
     setter!(set_filename, filename, &str, filename.to_string());
     setter!(set_lang, lang, Language);
     setter!(set_line_pos, line_pos, (usize, usize));
+    setter!(set_overwrite, overwrite, bool);
+    setter!(set_recording, recording, bool);
+    setter!(set_indent, indent, (Indent, usize));
 
     pub fn from_buffer(buf: &TextBuffer, buf_pos: (usize, usize)) -> Self {
         Self {
@@ -43,6 +49,9 @@ impl StatusBar {
             lang: buf.lang(),
             buf_pos,
             line_pos: (buf.cy() + 1, buf.rows().len()),
+            overwrite: buf.overwrite(),
+            recording: false,
+            indent: (buf.indent(), buf.tab_stop()),
             redraw: false,
         }
     }
@@ -59,7 +68,13 @@ impl StatusBar {
 
     pub fn right(&self) -> String {
         let (lang, (y, len)) = (self.lang, self.line_pos);
-        format!("{} {}/{}", lang.name(), y, len)
+        let rec = if self.recording { "REC " } else { "" };
+        let ovr = if self.overwrite { "OVR " } else { "" };
+        let indent = match self.indent {
+            (Indent::AsIs, tab_stop) => format!("tab:{}", tab_stop),
+            (Indent::Spaces(width), _) => format!("spaces:{}", width),
+        };
+        format!("{}{}{} {} {}/{}", rec, ovr, indent, lang.name(), y, len)
     }
 
     pub fn update_from_buf(&mut self, buf: &TextBuffer) {
@@ -67,5 +82,7 @@ impl StatusBar {
         self.set_lang(buf.lang());
         self.set_filename(buf.filename());
         self.set_line_pos((buf.cy() + 1, buf.rows().len()));
+        self.set_overwrite(buf.overwrite());
+        self.set_indent((buf.indent(), buf.tab_stop()));
     }
 }
@@ -15,6 +15,7 @@ pub enum Color {
     RedBG,
     YellowBG,
     OrangeBG,
+    GrayBG,
     NonText,
     Invert,
 }
@@ -22,7 +23,7 @@ pub enum Color {
 impl Color {
     pub fn has_bg_color(self) -> bool {
         use Color::*;
-        matches!(self, YellowBG | RedBG | OrangeBG)
+        matches!(self, YellowBG | RedBG | OrangeBG | GrayBG)
     }
 }
 
@@ -68,6 +69,11 @@ fn true_colors_sequence(color: Color) -> &'static [u8] {
             rgb_color!(bg, 0xd6, 0x5d, 0x0e),
         )
         .as_bytes(),
+        GrayBG => concat!(
+            rgb_color!(fg, 0xfb, 0xf1, 0xc7),
+            rgb_color!(bg, 0x66, 0x5c, 0x54),
+        )
+        .as_bytes(),
         NonText => rgb_color!(fg, 0x66, 0x5c, 0x54).as_bytes(),
         Invert => b"\x1b[7m",
     }
@@ -100,6 +106,7 @@ fn colors_256_sequence(color: Color) -> &'static [u8] {
         RedBG => b"\x1b[38;5;230m\x1b[48;5;124m",
         YellowBG => b"\x1b[38;5;235m\x1b[48;5;214m",
         OrangeBG => b"\x1b[38;5;235m\x1b[48;5;166m",
+        GrayBG => b"\x1b[38;5;230m\x1b[48;5;241m",
         NonText => b"\x1b[38;5;241m",
         Invert => b"\x1b[7m",
     }
@@ -121,6 +128,7 @@ fn colors_16_sequence(color: Color) -> &'static [u8] {
         RedBG => b"\x1b[97m\x1b[41m",
         YellowBG => b"\x1b[103m\x1b[30m",
         OrangeBG => b"\x1b[107m\x1b[30m", // White BG color is used instead of orange
+        GrayBG => b"\x1b[97m\x1b[100m",
         NonText => b"\x1b[37m",
         Invert => b"\x1b[7m",
     }
@@ -1,13 +1,18 @@
 use crate::edit_diff::{EditDiff, UndoRedo};
+use crate::editorconfig::{EditorConfig, FileFormat, LineEnding};
 use crate::error::Result;
+use crate::highlight::{self, CommentSyntax};
 use crate::history::History;
-use crate::language::{Indent, Language};
-use crate::row::Row;
+use crate::language::{Indent, IndentSetting, Language, MAX_INDENT_WIDTH};
+use crate::row::{self, Row};
 use std::cmp;
-use std::fs::File;
-use std::io::{self, BufRead, Write};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::mem;
+use std::ops;
 use std::path::{Path, PathBuf};
 use std::slice;
+use unicode_segmentation::UnicodeSegmentation;
 
 // Contain both actual path sequence and display string
 pub struct FilePath {
@@ -33,6 +38,185 @@ impl FilePath {
     }
 }
 
+fn leading_whitespace(line: &str) -> &str {
+    let len = line.len() - line.trim_start_matches(&[' ', '\t'][..]).len();
+    &line[..len]
+}
+
+// Prefix of line which is kept on each line when filling paragraph. It consists of indentation and
+// comment leader such as '// ', '# ' or ' * '
+fn fill_prefix(line: &str, lang: Language) -> &str {
+    let indent = leading_whitespace(line);
+    let rest = &line[indent.len()..];
+    let syntax = CommentSyntax::for_lang(lang);
+    let leader = match syntax.line {
+        Some(leader) if rest.starts_with(leader) => {
+            // Doc comments such as '///' or '//!'
+            let after = &rest[leader.len()..];
+            leader.len() + after.len() - after.trim_start_matches(&['/', '!', '#'][..]).len()
+        }
+        _ if syntax.block.is_some() && rest.starts_with('*') && !rest.starts_with("*/") => 1,
+        _ => 0,
+    };
+    let rest = &rest[leader..];
+    let spaces = rest.len() - rest.trim_start_matches(' ').len();
+    &line[..indent.len() + leader + spaces]
+}
+
+// Indentation which is one level less than given indentation
+fn dedent<'a>(indent: &'a str, unit: &str, tab_stop: usize) -> &'a str {
+    if let Some(dedented) = indent.strip_suffix(unit) {
+        return dedented;
+    }
+    if let Some(dedented) = indent.strip_suffix('\t') {
+        return dedented;
+    }
+    // Remove spaces as many as one level of indentation when indentation is not aligned with unit
+    let max = if unit == "\t" { tab_stop } else { unit.len() };
+    let spaces = indent.len() - indent.trim_end_matches(' ').len();
+    &indent[..indent.len() - cmp::min(spaces, max)]
+}
+
+// Display width of indentation
+fn indent_width(indent: &str, tab_stop: usize) -> usize {
+    indent.chars().fold(0, |width, c| match c {
+        '\t' => (width / tab_stop + 1) * tab_stop,
+        _ => width + 1,
+    })
+}
+
+// Guess indentation of text from leading whitespaces of lines. Width of indentation by spaces is
+// the most frequent increase of indentation between lines. Returns None when no line is indented
+fn detect_indent(rows: &[Row]) -> Option<Indent> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut increases = [0usize; MAX_INDENT_WIDTH + 1];
+    let mut prev = 0;
+    for row in rows {
+        let line = row.buffer();
+        let indent = leading_whitespace(line);
+        let rest = &line[indent.len()..];
+        if rest.is_empty() || rest.starts_with('*') {
+            continue; // Skip blank lines and lines in block comments such as ' * foo'
+        }
+        if indent.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let width = indent.len() - indent.trim_start_matches(' ').len();
+        if width > 0 {
+            spaces += 1;
+        }
+        if width > prev && width - prev <= MAX_INDENT_WIDTH {
+            increases[width - prev] += 1;
+        }
+        prev = width;
+    }
+
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs > spaces {
+        return Some(Indent::AsIs);
+    }
+    // Smaller width is preferred when counts are the same
+    let (width, &count) = increases
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|&(_, count)| count)?;
+    if count == 0 {
+        None
+    } else {
+        Some(Indent::Spaces(width))
+    }
+}
+
+fn closing_bracket(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '{' => Some('}'),
+        '[' => Some(']'),
+        _ => None,
+    }
+}
+
+// Kind of word segment. Adjacent segments of the same kind are joined into one word
+#[derive(Clone, Copy, PartialEq)]
+enum WordKind {
+    Space,
+    Punc,
+    Word,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+}
+
+impl WordKind {
+    fn new(segment: &str, word_chars: &[char]) -> Self {
+        if segment.chars().all(char::is_whitespace) {
+            WordKind::Space // Including U+3000 (ideographic space)
+        } else if segment
+            .chars()
+            .any(|c| c.is_alphanumeric() || c == '_' || word_chars.contains(&c))
+        {
+            WordKind::Word
+        } else {
+            WordKind::Punc
+        }
+    }
+
+    // Kind of character which splits a word segment further. UAX#29 joins words across some ASCII
+    // punctuations (e.g. 'foo.bar') and does not separate scripts in CJK text
+    fn of_char(c: char, word_chars: &[char]) -> Option<Self> {
+        match c {
+            c if c.is_ascii_punctuation() && c != '_' && !word_chars.contains(&c) => {
+                Some(WordKind::Punc)
+            }
+            '\u{3005}' | '\u{3007}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' => {
+                Some(WordKind::Han)
+            }
+            '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FA1F}' => Some(WordKind::Han),
+            '\u{3041}'..='\u{309F}' => Some(WordKind::Hiragana),
+            '\u{30A0}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' | '\u{FF66}'..='\u{FF9F}' => {
+                Some(WordKind::Katakana)
+            }
+            '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}' => {
+                Some(WordKind::Hangul)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Ranges of character indices of words in the line. Words are found by Unicode word boundaries
+// (UAX#29), then ASCII punctuations and runs of each script in CJK text (e.g. '漢字' and 'かな' in
+// '漢字かな') are separated, and adjacent segments of the same kind such as '-' and '>' are joined
+fn word_ranges(line: &str, word_chars: &[char]) -> Vec<ops::Range<usize>> {
+    let mut words: Vec<(ops::Range<usize>, WordKind)> = vec![];
+    let mut x = 0;
+    for segment in line.split_word_bounds() {
+        let kind = WordKind::new(segment, word_chars);
+        for cluster in segment.graphemes(true) {
+            let range = x..x + cluster.chars().count();
+            x = range.end;
+            if kind == WordKind::Space {
+                continue;
+            }
+            let kind = cluster
+                .chars()
+                .next()
+                .and_then(|c| WordKind::of_char(c, word_chars))
+                .unwrap_or(kind);
+            match words.last_mut() {
+                Some((last, k)) if *k == kind && last.end == range.start => last.end = range.end,
+                _ => words.push((range, kind)),
+            }
+        }
+    }
+    words.into_iter().map(|(r, _)| r).collect()
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum CursorDir {
     Left,
@@ -41,6 +225,55 @@ pub enum CursorDir {
     Down,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Lexical,
+    Numeric,
+    IgnoreCase,
+}
+
+// Number at start of line for sorting lines numerically. Lines which don't start with number are
+// treated as 0 like `sort -n`
+fn leading_number(line: &str) -> f64 {
+    let line = line.trim_start();
+    let len = line
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || i == 0 && (c == '-' || c == '+')))
+        .map(|(i, _)| i)
+        .unwrap_or(line.len());
+    line[..len].parse().unwrap_or(0.0)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaseConversion {
+    Upcase,
+    Downcase,
+    Capitalize,
+}
+
+impl CaseConversion {
+    fn convert(self, s: &str) -> String {
+        match self {
+            CaseConversion::Upcase => s.to_uppercase(),
+            CaseConversion::Downcase => s.to_lowercase(),
+            CaseConversion::Capitalize => {
+                // Upcase the first letter of each word and downcase the rest
+                let mut converted = String::with_capacity(s.len());
+                let mut in_word = false;
+                for c in s.chars() {
+                    if in_word {
+                        converted.extend(c.to_lowercase());
+                    } else {
+                        converted.extend(c.to_uppercase());
+                    }
+                    in_word = c.is_alphanumeric() || c == '_';
+                }
+                converted
+            }
+        }
+    }
+}
+
 pub struct Lines<'a>(slice::Iter<'a, Row>);
 
 impl<'a> Iterator for Lines<'a> {
@@ -83,6 +316,23 @@ pub struct TextBuffer {
     // Flag to require screen update
     // TODO: Merge with Screen's dirty_start field by using RenderContext struct
     dirty_start: Option<usize>,
+    // Position of mark. Region is between the mark and the cursor
+    mark: Option<(usize, usize)>,
+    // Positions of secondary cursors to edit multiple places at once. They are sorted in order of
+    // the buffer and never contain the primary cursor (cx, cy)
+    cursors: Vec<(usize, usize)>,
+    // Typed characters replace characters under the cursor in overwrite mode
+    overwrite: bool,
+    // Width of tab stop to render tab characters
+    tab_stop: usize,
+    // Indentation set for this buffer. When None, indentation of the language is used
+    indent: Option<Indent>,
+    // Indentation guessed from contents of file. This is used when no indentation is configured
+    detected_indent: Option<Indent>,
+    // Indentation and tab stop given by .editorconfig
+    editorconfig_indent: IndentSetting,
+    // Line ending, charset and so on to save the buffer
+    format: FileFormat,
 }
 
 impl TextBuffer {
@@ -98,6 +348,14 @@ impl TextBuffer {
             history: History::default(),
             inserted_undo: false,
             dirty_start: Some(0), // Ensure to render first screen
+            mark: None,
+            cursors: vec![],
+            overwrite: false,
+            tab_stop: row::DEFAULT_TAB_STOP,
+            indent: None,
+            detected_indent: None,
+            editorconfig_indent: IndentSetting::default(),
+            format: FileFormat::default(),
         }
     }
 
@@ -113,39 +371,63 @@ impl TextBuffer {
             history: History::default(),
             inserted_undo: false,
             dirty_start: Some(0), // Ensure to render first screen
+            mark: None,
+            cursors: vec![],
+            overwrite: false,
+            tab_stop: row::DEFAULT_TAB_STOP,
+            indent: None,
+            detected_indent: None,
+            editorconfig_indent: IndentSetting::default(),
+            format: FileFormat::default(),
         })
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
-        let file = Some(FilePath::from(path));
-        if !path.exists() {
-            // When the path does not exist, consider it as a new file
-            let mut buf = Self::empty();
-            buf.file = file;
-            buf.undo_count = 0;
-            buf.modified = false;
-            buf.lang = Language::detect(path);
-            return Ok(buf);
-        }
-
-        let row = io::BufReader::new(File::open(path)?)
-            .lines()
-            .map(|r| Row::new(r?))
-            .collect::<Result<_>>()?;
+        let lang = Language::detect(path);
+        let config = EditorConfig::load(path);
+        let format = config.file_format();
+        let editorconfig_indent = config.indent_setting(lang.indent());
+
+        let mut buf = if path.exists() {
+            let bytes = fs::read(path)?;
+            let text = format.charset.decode(&bytes).ok_or_else(|| {
+                let msg = format!("File is not valid {} text", format.charset.name());
+                io::Error::new(io::ErrorKind::InvalidData, msg)
+            })?;
+            let tab_stop = editorconfig_indent
+                .tab_stop
+                .unwrap_or(row::DEFAULT_TAB_STOP);
+            let row = if format.line_ending == LineEnding::Cr {
+                let text = text.strip_suffix('\r').unwrap_or(&text);
+                if text.is_empty() {
+                    vec![]
+                } else {
+                    text.split('\r')
+                        .map(|l| Row::with_tab_stop(l, tab_stop))
+                        .collect::<Result<_>>()?
+                }
+            } else {
+                text.lines()
+                    .map(|l| Row::with_tab_stop(l, tab_stop))
+                    .collect::<Result<_>>()?
+            };
+            Self {
+                detected_indent: detect_indent(&row),
+                row,
+                tab_stop,
+                ..Self::empty()
+            }
+        } else {
+            Self::empty() // When the path does not exist, consider it as a new file
+        };
 
-        Ok(Self {
-            cx: 0,
-            cy: 0,
-            file,
-            row,
-            undo_count: 0,
-            modified: false,
-            lang: Language::detect(path),
-            history: History::default(),
-            inserted_undo: false,
-            dirty_start: Some(0),
-        })
+        buf.file = Some(FilePath::from(path));
+        buf.lang = lang;
+        buf.format = format;
+        buf.editorconfig_indent = editorconfig_indent;
+        buf.set_indent_setting(editorconfig_indent);
+        Ok(buf)
     }
 
     fn set_dirty_start(&mut self, line: usize) {
@@ -158,7 +440,7 @@ impl TextBuffer {
     }
 
     fn apply_diff(&mut self, diff: &EditDiff, which: UndoRedo) {
-        let (x, y) = diff.apply(&mut self.row, which);
+        let (x, y) = diff.apply(&mut self.row, which, self.tab_stop);
         self.set_cursor(x, y);
         self.set_dirty_start(y);
     }
@@ -197,12 +479,115 @@ impl TextBuffer {
         self.new_diff(EditDiff::InsertChar(self.cx, self.cy, ch));
     }
 
+    // Replace the character under the cursor with the character. At end of line, the character is
+    // simply inserted
+    pub fn overwrite_char(&mut self, ch: char) {
+        if let Some(row) = self.row.get(self.cy) {
+            if self.cx < row.len() {
+                // Replace the whole grapheme cluster including its combining characters
+                let end = row.next_boundary(self.cx);
+                let replaced = row[self.cx..end].to_owned();
+                self.new_diff(EditDiff::Remove(end, self.cy, replaced));
+            }
+        }
+        self.insert_char(ch);
+    }
+
+    // Insert character with its closing pair. When `pairing` is false, only stepping over closing
+    // character is done and closing bracket does not dedent the line. It should be false when the
+    // cursor is in string literal or comment.
+    pub fn insert_char_auto_pair(&mut self, ch: char, pairing: bool) {
+        let row = self.row.get(self.cy);
+        let prev = row.and_then(|r| self.cx.checked_sub(1).map(|x| r.char_at(x)));
+        let next = row.and_then(|r| r.char_at_checked(self.cx));
+
+        let is_closer =
+            matches!(ch, ')' | ']' | '}') || highlight::closing_pair(self.lang, ch) == Some(ch);
+        if next == Some(ch) && is_closer && highlight::closing_pair(self.lang, '(').is_some() {
+            self.move_cursor_one(CursorDir::Right); // Step over the closing character
+            return;
+        }
+        if pairing && matches!(ch, '}' | ')' | ']') {
+            self.dedent_before_closer();
+        }
+
+        let close = match highlight::closing_pair(self.lang, ch) {
+            Some(close) if pairing => close,
+            _ => return self.insert_char(ch),
+        };
+        // Avoid pairing on typing quote in a word such as "don't" or typing bracket before a word
+        let next_is_word = next.map(|c| c.is_alphanumeric() || c == '_') == Some(true);
+        let prev_is_word = prev.map(|c| c.is_alphanumeric() || c == '_') == Some(true);
+        if next_is_word || close == ch && prev_is_word {
+            return self.insert_char(ch);
+        }
+
+        self.insert_char(ch);
+        self.new_diff(EditDiff::InsertChar(self.cx, self.cy, close));
+        self.cx -= 1;
+    }
+
+    // When closing bracket is input at the start of line, the line should be dedented
+    fn dedent_before_closer(&mut self) {
+        if !self.lang.has_blocks() || self.cy == self.row.len() {
+            return;
+        }
+        let indent = &self.row[self.cy][..self.cx];
+        if indent.is_empty() || leading_whitespace(indent).len() != indent.len() {
+            return;
+        }
+        let dedented = dedent(indent, self.indent().unit(), self.tab_stop);
+        let removed = indent[dedented.len()..].to_owned();
+        if !removed.is_empty() {
+            self.new_diff(EditDiff::Remove(self.cx, self.cy, removed));
+        }
+    }
+
+    // Insert text which may contain newlines at cursor as one undo point
+    pub fn insert_str<S: AsRef<str>>(&mut self, s: S) {
+        let s = s.as_ref();
+        if s.is_empty() {
+            return;
+        }
+        self.insert_undo_point();
+        if self.cy == self.row.len() {
+            self.new_diff(EditDiff::Newline);
+        }
+
+        let mut lines = s.split('\n');
+        let first = lines.next().unwrap();
+        let last = if let Some(last) = lines.next_back() {
+            last
+        } else {
+            self.new_diff(EditDiff::Insert(self.cx, self.cy, first.to_string()));
+            return;
+        };
+
+        // Text after cursor is moved to the end of last inserted line
+        let truncated = self.row[self.cy][self.cx..].to_owned();
+        if !truncated.is_empty() {
+            self.new_diff(EditDiff::Truncate(self.cy, truncated.clone()));
+        }
+        if !first.is_empty() {
+            self.new_diff(EditDiff::Append(self.cy, first.to_string()));
+        }
+        for line in lines {
+            self.new_diff(EditDiff::InsertLine(self.cy + 1, line.to_string()));
+        }
+        self.new_diff(EditDiff::InsertLine(self.cy + 1, last.to_string()));
+        if truncated.is_empty() {
+            self.cx = self.row[self.cy].len();
+        } else {
+            self.new_diff(EditDiff::Append(self.cy, truncated));
+        }
+    }
+
     pub fn insert_tab(&mut self) {
         self.insert_undo_point();
-        match self.lang.indent() {
+        match self.indent() {
             Indent::AsIs => self.insert_char('\t'),
-            Indent::Fixed(indent) => {
-                self.new_diff(EditDiff::Insert(self.cx, self.cy, indent.to_owned()));
+            indent => {
+                self.new_diff(EditDiff::Insert(self.cx, self.cy, indent.unit().to_owned()));
             }
         }
     }
@@ -222,15 +607,37 @@ impl TextBuffer {
         self.concat_next_line();
     }
 
-    pub fn delete_char(&mut self) {
+    // Delete the character before the cursor. When `pairing` is true, closing character of empty
+    // pair like (|) is also deleted. It should be false when the cursor is in string literal or
+    // comment.
+    pub fn delete_char(&mut self, pairing: bool) {
+        self.delete_prev_char(pairing);
+    }
+
+    fn delete_prev_char(&mut self, delete_pair: bool) {
         if self.cy == self.row.len() || self.cx == 0 && self.cy == 0 {
             return;
         }
         self.insert_undo_point();
         if self.cx > 0 {
-            let idx = self.cx - 1;
-            let deleted = self.row[self.cy].char_at(idx);
+            let row = &self.row[self.cy];
+            let start = row.prev_boundary(self.cx);
+            if start + 1 < self.cx {
+                // Delete whole grapheme cluster such as 'e' + U+0301
+                let deleted = row[start..self.cx].to_owned();
+                self.new_diff(EditDiff::Remove(self.cx, self.cy, deleted));
+                return;
+            }
+            let deleted = row.char_at(self.cx - 1);
+            let next = row.char_at_checked(self.cx);
+            let close = highlight::closing_pair(self.lang, deleted);
             self.new_diff(EditDiff::DeleteChar(self.cx, self.cy, deleted));
+            if let (true, Some(next), Some(close)) = (delete_pair, next, close) {
+                if next == close {
+                    // Delete both characters of empty pair like (|)
+                    self.new_diff(EditDiff::DeleteChar(self.cx + 1, self.cy, close));
+                }
+            }
         } else {
             self.squash_to_previous_line();
         }
@@ -273,18 +680,123 @@ impl TextBuffer {
         }
         self.insert_undo_point();
 
-        let mut x = self.cx - 1;
+        // Remove until the start of the word before the cursor
+        let x = word_ranges(self.row[self.cy].buffer(), self.lang.word_chars())
+            .into_iter()
+            .rev()
+            .find(|w| w.start < self.cx)
+            .map(|w| w.start)
+            .unwrap_or(0);
+
+        let removed = self.row[self.cy][x..self.cx].to_owned();
+        self.new_diff(EditDiff::Remove(self.cx, self.cy, removed));
+    }
+
+    // Delete characters from the cursor to the end of the next word. At end of line, the next line
+    // is concatenated to the line
+    pub fn delete_word_forward(&mut self) {
+        if self.cy == self.row.len() {
+            return;
+        }
         let row = &self.row[self.cy];
-        while x > 0 && row.char_at(x).is_ascii_whitespace() {
-            x -= 1;
+        let len = row.len();
+        if self.cx >= len {
+            if self.cy + 1 < self.row.len() {
+                self.insert_undo_point();
+                self.concat_next_line();
+            }
+            return;
         }
-        // `x - 1` since x should stop at the last non-whitespace character to remove
-        while x > 0 && !row.char_at(x - 1).is_ascii_whitespace() {
-            x -= 1;
+
+        // Remove until the end of the word after the cursor
+        let x = word_ranges(row.buffer(), self.lang.word_chars())
+            .into_iter()
+            .find(|w| w.end > self.cx)
+            .map(|w| w.end)
+            .unwrap_or(len);
+
+        let removed = row[self.cx..x].to_owned();
+        self.insert_undo_point();
+        self.new_diff(EditDiff::Remove(x, self.cy, removed));
+    }
+
+    // Delete the word before the cursor. At head of line, the line is concatenated to the previous
+    // line
+    pub fn delete_word_backward(&mut self) {
+        if self.cx > 0 {
+            self.delete_word();
+        } else if self.cy > 0 && self.cy < self.row.len() {
+            self.insert_undo_point();
+            self.squash_to_previous_line();
         }
+    }
 
-        let removed = self.row[self.cy][x..self.cx].to_owned();
-        self.new_diff(EditDiff::Remove(self.cx, self.cy, removed));
+    // Delete the line at the cursor including its newline. The cursor moves to the head of the
+    // next line
+    pub fn delete_whole_line(&mut self) {
+        if self.cy == self.row.len() {
+            return;
+        }
+        let removed = self.row[self.cy].buffer().to_owned();
+        if self.row.len() == 1 {
+            // The last line is cleared since text buffer must have at least one line
+            if !removed.is_empty() {
+                self.insert_undo_point();
+                self.new_diff(EditDiff::Truncate(0, removed));
+            }
+        } else {
+            // Applying DeleteLine moves the cursor to the end of the previous line
+            let y = self.cy;
+            self.insert_undo_point();
+            self.new_diff(EditDiff::DeleteLine(y, removed));
+            self.cy = cmp::min(y, self.row.len() - 1);
+        }
+        self.cx = 0;
+    }
+
+    // Delete characters from the cursor until the next occurrence of `ch` (inclusive) which may be
+    // in following lines. Returns false when `ch` is not found.
+    pub fn zap_to_char(&mut self, ch: char) -> bool {
+        let (cx, cy) = (self.cx, self.cy);
+        let found = self.row.iter().enumerate().skip(cy).find_map(|(y, row)| {
+            let from = if y == cy { cx } else { 0 };
+            let x = row[cmp::min(from, row.len())..]
+                .chars()
+                .position(|c| c == ch)?;
+            Some((from + x + 1, y))
+        });
+        let end = match found {
+            Some(end) => end,
+            None => return false,
+        };
+        self.insert_undo_point();
+        self.delete_range((cx, cy), end);
+        true
+    }
+
+    // Delete text between `start` and `end`. `start` must not be after `end`
+    fn delete_range(&mut self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) {
+        if sy == ey {
+            let removed = self.row[sy][sx..ex].to_owned();
+            self.new_diff(EditDiff::Remove(ex, sy, removed));
+            return;
+        }
+
+        let truncated = self.row[sy][sx..].to_owned();
+        if !truncated.is_empty() {
+            self.new_diff(EditDiff::Truncate(sy, truncated));
+        }
+        for _ in sy + 1..ey {
+            let removed = self.row[sy + 1].buffer().to_owned();
+            self.new_diff(EditDiff::DeleteLine(sy + 1, removed));
+        }
+        let last = self.row[sy + 1].buffer().to_owned();
+        let rest = self.row[sy + 1][ex..].to_owned();
+        self.new_diff(EditDiff::DeleteLine(sy + 1, last));
+        if !rest.is_empty() {
+            self.new_diff(EditDiff::Append(sy, rest));
+        }
+        self.set_cursor(sx, sy);
     }
 
     pub fn delete_right_char(&mut self) {
@@ -295,20 +807,109 @@ impl TextBuffer {
             return;
         }
         self.move_cursor_one(CursorDir::Right);
-        self.delete_char();
+        self.delete_prev_char(false);
     }
 
     pub fn insert_line(&mut self) {
         self.insert_undo_point();
         if self.cy >= self.row.len() {
             self.new_diff(EditDiff::Newline);
-        } else if self.cx >= self.row[self.cy].len() {
-            self.new_diff(EditDiff::InsertLine(self.cy + 1, "".to_string()));
-        } else if self.cx <= self.row[self.cy].buffer().len() {
-            let truncated = self.row[self.cy][self.cx..].to_owned();
+            return;
+        }
+
+        // New line inherits indentation of current line. It is indented one more level when the
+        // current line opens a new block.
+        let before = &self.row[self.cy][..self.cx];
+        let base_indent = leading_whitespace(before).to_owned();
+        let mut indent = base_indent.clone();
+        let opens_block = self.lang.opens_block(before);
+        if opens_block {
+            indent.push_str(self.indent().unit());
+        }
+        let closer = before.trim_end().chars().last().and_then(closing_bracket);
+
+        let truncated = self.row[self.cy][self.cx..].to_owned();
+        let rest = truncated.trim_start();
+        if !truncated.is_empty() {
             self.new_diff(EditDiff::Truncate(self.cy, truncated.clone()));
-            self.new_diff(EditDiff::InsertLine(self.cy + 1, truncated));
         }
+
+        let y = self.cy + 1;
+        if opens_block && closer.is_some() && rest.starts_with(closer.unwrap()) {
+            // Cursor is between brackets like {|}. Put the closing bracket on its own line
+            self.new_diff(EditDiff::InsertLine(y, format!("{}{}", base_indent, rest)));
+            self.new_diff(EditDiff::InsertLine(y, "".to_string()));
+        } else {
+            self.new_diff(EditDiff::InsertLine(y, rest.to_string()));
+        }
+        if !indent.is_empty() {
+            self.new_diff(EditDiff::Insert(0, y, indent));
+        }
+    }
+
+    // Indent lines in region (or current line when mark is not set) following previous lines
+    pub fn reindent(&mut self) {
+        let lines = self.take_region_lines();
+        if lines.start == lines.end {
+            return;
+        }
+        self.insert_undo_point();
+
+        let (cx, cy) = (self.cx, self.cy);
+        let mut new_cx = cx;
+        let unit = self.indent().unit();
+        for y in lines {
+            let line = self.row[y].buffer();
+            let current = leading_whitespace(line).to_owned();
+            if current.len() == line.len() {
+                continue; // Skip blank line not to add trailing whitespaces
+            }
+
+            let prev = self.row[..y]
+                .iter()
+                .map(Row::buffer)
+                .rev()
+                .find(|l| !l.trim().is_empty());
+            let mut want = match prev {
+                Some(prev) if self.lang.opens_block(prev) => {
+                    format!("{}{}", leading_whitespace(prev), unit)
+                }
+                // Indenting the line more may move it into the block above (e.g. Python)
+                Some(prev) if !self.lang.has_blocks() => {
+                    let prev = leading_whitespace(prev);
+                    if indent_width(prev, self.tab_stop) > indent_width(&current, self.tab_stop) {
+                        current.clone()
+                    } else {
+                        prev.to_owned()
+                    }
+                }
+                Some(prev) => leading_whitespace(prev).to_owned(),
+                None => "".to_string(),
+            };
+            if self.lang.closes_block(line) {
+                want.truncate(dedent(&want, unit, self.tab_stop).len());
+            }
+            if want == current {
+                continue;
+            }
+
+            let (current_len, want_len) = (current.chars().count(), want.chars().count());
+            if y == cy {
+                new_cx = if cx <= current_len {
+                    want_len
+                } else {
+                    cx - current_len + want_len
+                };
+            }
+            if !current.is_empty() {
+                self.new_diff(EditDiff::Remove(current_len, y, current));
+            }
+            if !want.is_empty() {
+                self.new_diff(EditDiff::Insert(0, y, want));
+            }
+        }
+
+        self.set_cursor(new_cx, cy);
     }
 
     pub fn move_cursor_one(&mut self, dir: CursorDir) {
@@ -316,7 +917,7 @@ impl TextBuffer {
             CursorDir::Up => self.cy = self.cy.saturating_sub(1),
             CursorDir::Left => {
                 if self.cx > 0 {
-                    self.cx -= 1;
+                    self.cx = self.row[self.cy].prev_boundary(self.cx);
                 } else if self.cy > 0 {
                     // When moving to left at top of line, move cursor to end of previous line
                     self.cy -= 1;
@@ -336,7 +937,7 @@ impl TextBuffer {
                     if self.cx < len {
                         // Allow to move cursor until next col to the last col of line to enable to
                         // add a new character at the end of line.
-                        self.cx += 1;
+                        self.cx = self.row[self.cy].next_boundary(self.cx);
                     } else if self.cx >= len {
                         // When moving to right at the end of line, move cursor to top of next line.
                         self.cy += 1;
@@ -347,9 +948,15 @@ impl TextBuffer {
         };
 
         // Snap cursor to end of line when moving up/down from longer line
-        let len = self.row.get(self.cy).map(Row::len).unwrap_or(0);
-        if self.cx > len {
-            self.cx = len;
+        if let Some(row) = self.row.get(self.cy) {
+            if self.cx > row.len() {
+                self.cx = row.len();
+            } else if !row.is_boundary(self.cx) {
+                // Cursor must not be put at the middle of grapheme cluster
+                self.cx = row.prev_boundary(self.cx);
+            }
+        } else {
+            self.cx = 0;
         }
     }
 
@@ -380,62 +987,44 @@ impl TextBuffer {
     }
 
     pub fn move_cursor_by_word(&mut self, dir: CursorDir) {
-        #[derive(PartialEq)]
-        enum CharKind {
-            Ident,
-            Punc,
-            Space,
-        }
-
-        impl CharKind {
-            fn new_at(rows: &[Row], x: usize, y: usize) -> Self {
-                rows.get(y)
-                    .and_then(|r| r.char_at_checked(x))
-                    .map(|c| {
-                        if c.is_ascii_whitespace() {
-                            CharKind::Space
-                        } else if c == '_' || c.is_ascii_alphanumeric() {
-                            CharKind::Ident
-                        } else {
-                            CharKind::Punc
-                        }
-                    })
-                    .unwrap_or(CharKind::Space)
-            }
-        }
-
-        fn at_word_start(left: &CharKind, right: &CharKind) -> bool {
-            matches!(
-                (left, right),
-                (&CharKind::Space, &CharKind::Ident)
-                    | (&CharKind::Space, &CharKind::Punc)
-                    | (&CharKind::Punc, &CharKind::Ident)
-                    | (&CharKind::Ident, &CharKind::Punc)
-            )
-        }
-
-        self.move_cursor_one(dir);
-        let mut prev = CharKind::new_at(&self.row, self.cx, self.cy);
-        self.move_cursor_one(dir);
-        let mut current = CharKind::new_at(&self.row, self.cx, self.cy);
-
-        loop {
-            if self.cy == 0 && self.cx == 0 || self.cy == self.row.len() {
-                return;
+        debug_assert!(dir == CursorDir::Left || dir == CursorDir::Right);
+        let word_chars = self.lang.word_chars();
+        let words = |row: &Row| word_ranges(row.buffer(), word_chars);
+        if dir == CursorDir::Right {
+            // Find the start of word after the cursor. In following lines, word at head of line
+            // is the next word
+            let mut min = self.cx + 1;
+            while self.cy < self.row.len() {
+                if let Some(w) = words(&self.row[self.cy])
+                    .into_iter()
+                    .find(|w| w.start >= min)
+                {
+                    self.cx = w.start;
+                    return;
+                }
+                self.cy += 1;
+                self.cx = 0;
+                min = 0;
             }
-
-            match dir {
-                CursorDir::Right if at_word_start(&prev, &current) => return,
-                CursorDir::Left if at_word_start(&current, &prev) => {
-                    self.move_cursor_one(CursorDir::Right); // Adjust cursor position to start of word
+        } else {
+            // Find the start of word before the cursor
+            let mut max = self.cx;
+            loop {
+                if let Some(w) = self
+                    .row
+                    .get(self.cy)
+                    .and_then(|r| words(r).into_iter().rev().find(|w| w.start < max))
+                {
+                    self.cx = w.start;
+                    return;
+                }
+                if self.cy == 0 {
+                    self.cx = 0;
                     return;
                 }
-                _ => {}
+                self.cy -= 1;
+                max = usize::MAX;
             }
-
-            prev = current;
-            self.move_cursor_one(dir);
-            current = CharKind::new_at(&self.row, self.cx, self.cy);
         }
     }
 
@@ -453,6 +1042,761 @@ impl TextBuffer {
         }
     }
 
+    // Range of lines in the paragraph at the cursor. It is found by paragraph motion
+    fn paragraph_at_cursor(&mut self) -> ops::Range<usize> {
+        let (cx, cy) = (self.cx, self.cy);
+        self.move_cursor_paragraph(CursorDir::Down);
+        let mut end = self.cy;
+        self.move_cursor_paragraph(CursorDir::Up);
+        let start = self.cy;
+        self.set_cursor(cx, cy);
+        // Exclude empty lines between the paragraph and the next one
+        while end > start && self.row[end - 1].buffer().is_empty() {
+            end -= 1;
+        }
+        start..end
+    }
+
+    // Re-wrap the paragraph at the cursor so that each line fits in `width` columns. When `width`
+    // is None, the paragraph is joined into one line. Lines in a paragraph share the same prefix
+    // (indentation and comment leader) and the prefix is kept on each filled line.
+    pub fn fill_paragraph(&mut self, width: Option<usize>) {
+        let (cx, cy) = (self.cx, self.cy);
+        let lang = self.lang;
+        let prefix = match self.row.get(cy) {
+            Some(row) => fill_prefix(row.buffer(), lang).to_owned(),
+            None => return,
+        };
+        let in_paragraph = |row: &Row| {
+            let line = row.buffer();
+            line.len() > prefix.len() && fill_prefix(line, lang) == prefix
+        };
+        if !in_paragraph(&self.row[cy]) {
+            return;
+        }
+        // Lines with other prefix such as code before comment are not filled
+        let paragraph = self.paragraph_at_cursor();
+        let mut start = cy;
+        while start > paragraph.start && in_paragraph(&self.row[start - 1]) {
+            start -= 1;
+        }
+        let mut end = cy + 1;
+        while end < paragraph.end && in_paragraph(&self.row[end]) {
+            end += 1;
+        }
+
+        // Count non-whitespace characters before the cursor to put the cursor after the same
+        // character in the filled paragraph
+        let prefix_len = prefix.chars().count();
+        let mut offset = 0;
+        for y in start..=cy {
+            let row = &self.row[y];
+            let to = if y == cy {
+                cmp::min(cx, row.len())
+            } else {
+                row.len()
+            };
+            let from = cmp::min(prefix_len, to);
+            offset += row[from..to].chars().filter(|c| !c.is_whitespace()).count();
+        }
+
+        let prefix_width = self.row[cy].rx_from_cx(prefix_len);
+        let mut lines = vec![];
+        let mut line = prefix.clone();
+        let mut line_width = prefix_width;
+        for word in
+            (start..end).flat_map(|y| self.row[y].buffer()[prefix.len()..].split_whitespace())
+        {
+            let word_width = row::display_width(word);
+            if line.len() > prefix.len() {
+                let fits = match width {
+                    Some(w) => line_width + 1 + word_width <= w,
+                    None => true,
+                };
+                if fits {
+                    line.push(' ');
+                    line_width += 1;
+                } else {
+                    lines.push(mem::replace(&mut line, prefix.clone()));
+                    line_width = prefix_width;
+                }
+            }
+            line.push_str(word);
+            line_width += word_width;
+        }
+        lines.push(line);
+
+        if lines
+            .iter()
+            .eq(self.row[start..end].iter().map(Row::buffer))
+        {
+            return;
+        }
+        self.insert_undo_point();
+
+        let mut cursor = (prefix_len, start);
+        if offset > 0 {
+            let mut count = 0;
+            'lines: for (i, line) in lines.iter().enumerate() {
+                for (x, c) in line[prefix.len()..].chars().enumerate() {
+                    if !c.is_whitespace() {
+                        count += 1;
+                    }
+                    if count == offset {
+                        cursor = (prefix_len + x + 1, start + i);
+                        break 'lines;
+                    }
+                }
+            }
+        }
+
+        self.replace_lines(start..end, lines);
+        self.set_cursor(cursor.0, cursor.1);
+    }
+
+    // Swap the character before the cursor and the character at the cursor, then move the cursor
+    // forward. At end of line, the last two characters are swapped. Characters are grapheme
+    // clusters so that combining characters are moved with their base characters.
+    pub fn transpose_chars(&mut self) {
+        let row = match self.row.get(self.cy) {
+            Some(row) => row,
+            None => return,
+        };
+        let len = row.len();
+        let x = if self.cx < len {
+            self.cx
+        } else {
+            row.prev_boundary(len)
+        };
+        if self.cx == 0 || x == 0 {
+            return;
+        }
+        let (start, end) = (row.prev_boundary(x), row.next_boundary(x));
+        let (prev, next) = (&row[start..x], &row[x..end]);
+        let (removed, inserted) = (format!("{}{}", prev, next), format!("{}{}", next, prev));
+        let y = self.cy;
+        self.insert_undo_point();
+        self.new_diff(EditDiff::Remove(end, y, removed));
+        self.new_diff(EditDiff::Insert(start, y, inserted));
+    }
+
+    // Swap the word at (or before) the cursor and the next word in the line. When the word is the
+    // last one, the last two words are swapped. The cursor moves to the end of the swapped words.
+    pub fn transpose_words(&mut self) {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        let chars: Vec<_> = match self.row.get(self.cy) {
+            Some(row) => row.buffer().chars().collect(),
+            None => return,
+        };
+
+        let mut words = vec![];
+        let mut x = 0;
+        while x < chars.len() {
+            if is_word_char(chars[x]) {
+                let start = x;
+                while x < chars.len() && is_word_char(chars[x]) {
+                    x += 1;
+                }
+                words.push(start..x);
+            } else {
+                x += 1;
+            }
+        }
+        if words.len() < 2 {
+            return;
+        }
+
+        let i = words.iter().rposition(|w| w.start < self.cx).unwrap_or(0);
+        let i = cmp::min(i, words.len() - 2);
+        let (first, second) = (words[i].clone(), words[i + 1].clone());
+
+        self.insert_undo_point();
+        let collect = |r: ops::Range<usize>| chars[r].iter().collect::<String>();
+        let swapped =
+            collect(second.clone()) + &collect(first.end..second.start) + &collect(first.clone());
+        let y = self.cy;
+        self.new_diff(EditDiff::Remove(
+            second.end,
+            y,
+            collect(first.start..second.end),
+        ));
+        self.new_diff(EditDiff::Insert(first.start, y, swapped));
+    }
+
+    // Move lines in region (or current line) up or down by one line. Mark is moved with the lines
+    // so that the same region can be moved repeatedly.
+    pub fn move_lines(&mut self, dir: CursorDir) {
+        debug_assert!(dir == CursorDir::Up || dir == CursorDir::Down);
+        let mark = self.mark;
+        let lines = self.take_region_lines();
+        if lines.start == lines.end
+            || dir == CursorDir::Up && lines.start == 0
+            || dir == CursorDir::Down && lines.end >= self.row.len()
+        {
+            self.mark = mark;
+            return;
+        }
+        self.insert_undo_point();
+
+        let (cx, cy) = (self.cx, self.cy);
+        // Cursor diffs surround the line diffs so that undo/redo put the cursor on the moved line
+        self.new_diff(EditDiff::Cursor(cx, cy));
+        // The line next to the region is moved to the other side of the region
+        let (cy, mark) = if dir == CursorDir::Up {
+            let above = self.row[lines.start - 1].buffer().to_owned();
+            self.new_diff(EditDiff::InsertLine(lines.end, above.clone()));
+            self.new_diff(EditDiff::DeleteLine(lines.start - 1, above));
+            (cy - 1, mark.map(|(x, y)| (x, y - 1)))
+        } else {
+            let below = self.row[lines.end].buffer().to_owned();
+            self.new_diff(EditDiff::InsertLine(lines.start, below.clone()));
+            self.new_diff(EditDiff::DeleteLine(lines.end + 1, below));
+            (cy + 1, mark.map(|(x, y)| (x, y + 1)))
+        };
+        self.mark = mark;
+        self.new_diff(EditDiff::Cursor(cx, cy));
+    }
+
+    // Insert a copy of lines in region (or current line) after them. The cursor moves to the
+    // copied line.
+    pub fn duplicate_lines(&mut self) {
+        let lines = self.take_region_lines();
+        if lines.start == lines.end {
+            return;
+        }
+        self.insert_undo_point();
+
+        let (cx, cy) = (self.cx, self.cy);
+        let copied: Vec<_> = lines
+            .clone()
+            .map(|y| self.row[y].buffer().to_owned())
+            .collect();
+        for (i, line) in copied.into_iter().enumerate() {
+            self.new_diff(EditDiff::InsertLine(lines.end + i, line));
+        }
+        let cy = if lines.contains(&cy) {
+            cy + lines.len()
+        } else {
+            cy
+        };
+        self.set_cursor(cx, cy);
+    }
+
+    // Convert case from the cursor to the end of the word or of the region when mark is set. When no
+    // region is active, the cursor moves to the end of the converted word.
+    pub fn convert_case(&mut self, conv: CaseConversion) {
+        let cursor = (self.cx, self.cy);
+        let in_region = self.mark.is_some();
+        let (start, end) = if let Some((mx, my)) = self.mark.take() {
+            if (my, mx) < (self.cy, self.cx) {
+                ((mx, my), cursor)
+            } else {
+                (cursor, (mx, my))
+            }
+        } else {
+            // Until the end of the word at (or after) the cursor. Punctuations are not words
+            let end = self.row.get(self.cy).map(|row| {
+                word_ranges(row.buffer(), self.lang.word_chars())
+                    .into_iter()
+                    .find(|w| {
+                        w.end > self.cx && row[w.start..w.end].chars().any(char::is_alphanumeric)
+                    })
+                    .map(|w| w.end)
+                    .unwrap_or_else(|| row.len())
+            });
+            (cursor, (end.unwrap_or(0), self.cy))
+        };
+        if start.1 >= self.row.len() {
+            return;
+        }
+        let last_y = cmp::min(end.1, self.row.len() - 1);
+
+        let mut new_end = end;
+        for y in start.1..=last_y {
+            let len = self.row[y].len();
+            let from = if y == start.1 {
+                cmp::min(start.0, len)
+            } else {
+                0
+            };
+            let to = if y == end.1 {
+                cmp::min(end.0, len)
+            } else {
+                len
+            };
+            let text = self.row[y][from..to].to_owned();
+            let converted = conv.convert(&text);
+            if y == end.1 {
+                new_end = (from + converted.chars().count(), y);
+            }
+            if text == converted {
+                continue;
+            }
+            self.insert_undo_point();
+            self.new_diff(EditDiff::Remove(to, y, text));
+            self.new_diff(EditDiff::Insert(from, y, converted));
+        }
+
+        // Length of text may be changed by the conversion (e.g. 'ß' -> 'SS')
+        let (x, y) = if in_region && cursor == start {
+            start
+        } else {
+            new_end
+        };
+        let len = self.row.get(y).map(Row::len).unwrap_or(0);
+        self.set_cursor(cmp::min(x, len), y);
+    }
+
+    // Comment out lines in region (or current line) or uncomment them when all of them are already
+    // commented. Returns false when the language has no comment syntax.
+    pub fn toggle_comment(&mut self) -> bool {
+        let syntax = CommentSyntax::for_lang(self.lang);
+        let (open, close) = match (syntax.line, syntax.block) {
+            (Some(leader), _) => (leader, ""),
+            (None, Some((start, end))) => (start, end),
+            (None, None) => return false,
+        };
+
+        let lines = self.take_region_lines();
+        let targets: Vec<_> = lines
+            .filter(|&y| !self.row[y].buffer().trim().is_empty())
+            .collect();
+        if targets.is_empty() {
+            return true;
+        }
+        self.insert_undo_point();
+
+        let is_commented = |line: &str| {
+            let line = line.trim();
+            line.len() >= open.len() + close.len()
+                && line.starts_with(open)
+                && line.ends_with(close)
+        };
+        let (cx, cy) = (self.cx, self.cy);
+        let mut new_cx = cx;
+
+        if targets.iter().all(|&y| is_commented(self.row[y].buffer())) {
+            for y in targets {
+                let line = self.row[y].buffer();
+                let start = leading_whitespace(line).chars().count();
+                let line = line.trim();
+                let inner = &line[open.len()..line.len() - close.len()];
+                let mut removed = open.to_string();
+                if inner.starts_with(' ') {
+                    removed.push(' ');
+                }
+                let removed_len = removed.chars().count();
+                self.new_diff(EditDiff::Remove(start + removed_len, y, removed));
+
+                if !close.is_empty() {
+                    let row = &self.row[y];
+                    let content = row.buffer().trim_end();
+                    let mut end = content.len() - close.len();
+                    if content[..end].ends_with(' ') {
+                        end -= 1;
+                    }
+                    let truncated = row.buffer()[end..].to_owned();
+                    self.new_diff(EditDiff::Truncate(y, truncated));
+                }
+                if y == cy && cx > start {
+                    new_cx = cmp::max(cx.saturating_sub(removed_len), start);
+                }
+            }
+        } else {
+            // Insert comment leaders at the same column which is minimum indentation of the lines
+            let indent = targets
+                .iter()
+                .map(|&y| leading_whitespace(self.row[y].buffer()).chars().count())
+                .min()
+                .unwrap();
+            let leader = format!("{} ", open);
+            let leader_len = leader.chars().count();
+            for y in targets {
+                self.new_diff(EditDiff::Insert(indent, y, leader.clone()));
+                if !close.is_empty() {
+                    self.new_diff(EditDiff::Append(y, format!(" {}", close)));
+                }
+                if y == cy && cx >= indent {
+                    new_cx = cx + leader_len;
+                }
+            }
+        }
+
+        let len = self.row.get(cy).map(Row::len).unwrap_or(0);
+        self.set_cursor(cmp::min(new_cx, len), cy);
+        true
+    }
+
+    // Replace lines in region with lines transformed by `f`. The cursor moves to the head of the
+    // region. Returns false when mark is not set.
+    fn transform_region_lines<F>(&mut self, f: F) -> bool
+    where
+        F: FnOnce(Vec<String>) -> Vec<String>,
+    {
+        if self.mark.is_none() {
+            return false;
+        }
+        let lines = self.take_region_lines();
+        let before: Vec<_> = self.row[lines.clone()]
+            .iter()
+            .map(|r| r.buffer().to_owned())
+            .collect();
+        let after = f(before.clone());
+        if after == before {
+            return true;
+        }
+        self.insert_undo_point();
+        self.replace_lines(lines.clone(), after);
+        self.set_cursor(0, lines.start);
+        true
+    }
+
+    // Sort lines in region. Returns false when mark is not set
+    pub fn sort_lines(&mut self, order: SortOrder, reverse: bool) -> bool {
+        self.transform_region_lines(|mut lines| {
+            match order {
+                SortOrder::Lexical => lines.sort(),
+                SortOrder::Numeric => lines.sort_by(|l, r| {
+                    leading_number(l)
+                        .partial_cmp(&leading_number(r))
+                        .unwrap_or(cmp::Ordering::Equal)
+                }),
+                SortOrder::IgnoreCase => lines.sort_by_cached_key(|l| l.to_lowercase()),
+            }
+            if reverse {
+                lines.reverse();
+            }
+            lines
+        })
+    }
+
+    // Remove duplicate adjacent lines in region. Returns false when mark is not set
+    pub fn uniq_lines(&mut self) -> bool {
+        self.transform_region_lines(|mut lines| {
+            lines.dedup();
+            lines
+        })
+    }
+
+    // Reverse order of lines in region. Returns false when mark is not set
+    pub fn reverse_lines(&mut self) -> bool {
+        self.transform_region_lines(|mut lines| {
+            lines.reverse();
+            lines
+        })
+    }
+
+    // Returns lines in region (or whole buffer when mark is not set) to be filtered and their text
+    pub fn lines_to_filter(&mut self) -> (ops::Range<usize>, String) {
+        let lines = match self.mark {
+            Some(_) => {
+                let mark = self.mark;
+                let lines = self.take_region_lines();
+                self.mark = mark;
+                lines
+            }
+            None => 0..self.row.len(),
+        };
+        let mut text = String::new();
+        for row in &self.row[lines.clone()] {
+            text.push_str(row.buffer());
+            text.push('\n');
+        }
+        (lines, text)
+    }
+
+    // Replace the lines returned from lines_to_filter() with output of the filter
+    pub fn replace_filtered_lines(&mut self, lines: ops::Range<usize>, output: &str) {
+        let mut filtered: Vec<_> = output.lines().map(str::to_owned).collect();
+        if filtered.is_empty() && lines.len() == self.row.len() {
+            filtered.push(String::new()); // Buffer must contain at least one line
+        }
+
+        self.mark = None;
+        self.insert_undo_point();
+        self.replace_lines(lines.clone(), filtered);
+        let y = cmp::min(lines.start, self.row.len() - 1);
+        self.set_cursor(0, y);
+    }
+
+    // Replace lines in the range with new lines
+    fn replace_lines(&mut self, range: ops::Range<usize>, lines: Vec<String>) {
+        // Insert new lines before deleting old ones not to make the buffer empty
+        for (i, line) in lines.into_iter().enumerate() {
+            self.new_diff(EditDiff::InsertLine(range.end + i, line));
+        }
+        for y in range.rev() {
+            let line = self.row[y].buffer().to_owned();
+            self.new_diff(EditDiff::DeleteLine(y, line));
+        }
+    }
+
+    pub fn has_mark(&self) -> bool {
+        self.mark.is_some()
+    }
+
+    // Returns true when mark is set
+    pub fn toggle_mark(&mut self) -> bool {
+        self.mark = if self.mark.is_some() {
+            None
+        } else {
+            Some((self.cx, self.cy))
+        };
+        self.mark.is_some()
+    }
+
+    // Returns range of lines in region between mark and cursor. When mark is not set, it only
+    // contains the cursor line. Mark is deactivated since the region is consumed.
+    fn take_region_lines(&mut self) -> ops::Range<usize> {
+        let len = self.row.len();
+        let (start, end) = match self.mark.take() {
+            Some((_, y)) if y < self.cy => (y, self.cy + 1),
+            Some((_, y)) => (self.cy, y + 1),
+            None => (self.cy, self.cy + 1),
+        };
+        cmp::min(start, len)..cmp::min(end, len)
+    }
+
+    fn offset_from_end(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let len = self.row.get(y).map(Row::len).unwrap_or(0);
+        (len.saturating_sub(x), self.row.len() - y)
+    }
+
+    fn pos_from_end(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let y = self.row.len().saturating_sub(y);
+        let len = self.row.get(y).map(Row::len).unwrap_or(0);
+        (len.saturating_sub(x), y)
+    }
+
+    fn add_cursor(&mut self, pos: (usize, usize)) {
+        if pos != (self.cx, self.cy) && !self.cursors.contains(&pos) {
+            self.cursors.push(pos);
+            self.cursors.sort_by_key(|&(x, y)| (y, x));
+        }
+    }
+
+    // Apply the operation at each cursor. Cursors are visited from the bottom of the buffer so
+    // that edits do not shift positions of cursors not visited yet. Positions of visited cursors
+    // are kept as offsets from the end of buffer since edits before them do not change the offsets.
+    pub fn for_each_cursor<F: FnMut(&mut Self)>(&mut self, mut f: F) {
+        if self.cursors.is_empty() {
+            return f(self);
+        }
+
+        let primary = (self.cx, self.cy);
+        let mut cursors = mem::take(&mut self.cursors);
+        cursors.push(primary);
+        cursors.sort_by_key(|&(x, y)| (y, x));
+
+        let mut visited = Vec::with_capacity(cursors.len());
+        for &(x, y) in cursors.iter().rev() {
+            self.set_cursor(x, y);
+            f(self);
+            visited.push((self.offset_from_end((self.cx, self.cy)), (x, y) == primary));
+        }
+
+        // Primary cursor must be set first since secondary cursors at the same position are merged
+        if let Some(&(offset, _)) = visited.iter().find(|(_, is_primary)| *is_primary) {
+            let (x, y) = self.pos_from_end(offset);
+            self.set_cursor(x, y);
+        }
+        for (offset, is_primary) in visited {
+            if !is_primary {
+                let pos = self.pos_from_end(offset);
+                self.add_cursor(pos);
+            }
+        }
+    }
+
+    pub fn secondary_cursors(&self) -> &[(usize, usize)] {
+        &self.cursors
+    }
+
+    // Returns true when some secondary cursors were removed
+    pub fn clear_secondary_cursors(&mut self) -> bool {
+        let had_cursors = !self.cursors.is_empty();
+        self.cursors.clear();
+        had_cursors
+    }
+
+    // Add a cursor at the next occurrence of the word under the primary cursor. The primary cursor
+    // moves to the occurrence and the previous position is kept as a secondary cursor. Returns
+    // false when the cursor is not on a word or no other occurrence is found.
+    pub fn add_cursor_at_next_word(&mut self) -> bool {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        let row = match self.row.get(self.cy) {
+            Some(row) => row,
+            None => return false,
+        };
+        let chars: Vec<_> = row.buffer().chars().collect();
+        let mut start = cmp::min(self.cx, chars.len());
+        while start > 0 && is_word_char(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = start;
+        while end < chars.len() && is_word_char(chars[end]) {
+            end += 1;
+        }
+        if start == end {
+            return false;
+        }
+        let word: String = chars[start..end].iter().collect();
+        let offset = self.cx - start;
+
+        // Search lines after the cursor at first then wrap around to the top of buffer
+        let len = self.row.len();
+        for i in 0..=len {
+            let y = (self.cy + i) % len;
+            let line = self.row[y].buffer();
+            let skip = if i == 0 { end } else { 0 };
+            let found = line
+                .char_indices()
+                .enumerate()
+                .skip(skip)
+                .filter(|(_, (byte_idx, _))| line[*byte_idx..].starts_with(&word))
+                .map(|(x, (byte_idx, _))| (x, byte_idx))
+                .find(|&(x, byte_idx)| {
+                    let before = line[..byte_idx].chars().next_back();
+                    let after = line[byte_idx + word.len()..].chars().next();
+                    let pos = (x + offset, y);
+                    !before.map(is_word_char).unwrap_or(false)
+                        && !after.map(is_word_char).unwrap_or(false)
+                        && pos != (self.cx, self.cy)
+                        && !self.cursors.contains(&pos)
+                });
+            if let Some((x, _)) = found {
+                let prev = (self.cx, self.cy);
+                self.set_cursor(x + offset, y);
+                self.add_cursor(prev);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Add a cursor on each line in region at the same column as the primary cursor. Returns false
+    // when no cursor was added.
+    pub fn add_cursors_on_region_lines(&mut self) -> bool {
+        let lines = self.take_region_lines();
+        let prev_len = self.cursors.len();
+        for y in lines {
+            let x = cmp::min(self.cx, self.row[y].len());
+            self.add_cursor((x, y));
+        }
+        self.cursors.len() != prev_len
+    }
+
+    // Returns lines and range of display columns of rectangle between mark and cursor. Mark is
+    // deactivated since the region is consumed. Returns None when mark is not set.
+    fn take_region_rectangle(&mut self) -> Option<(ops::Range<usize>, ops::Range<usize>)> {
+        let (mx, my) = self.mark?;
+        let rx_at = |(x, y): (usize, usize)| {
+            self.row
+                .get(y)
+                .map(|r| r.rx_from_cx(cmp::min(x, r.len())))
+                .unwrap_or(0)
+        };
+        let (from, to) = (rx_at((mx, my)), rx_at((self.cx, self.cy)));
+        let lines = self.take_region_lines();
+        Some((lines, cmp::min(from, to)..cmp::max(from, to)))
+    }
+
+    fn delete_rectangle(
+        &mut self,
+        lines: ops::Range<usize>,
+        cols: &ops::Range<usize>,
+    ) -> Vec<String> {
+        let mut deleted = Vec::with_capacity(lines.len());
+        for y in lines {
+            let row = &self.row[y];
+            let (start, end) = (row.cx_from_rx(cols.start), row.cx_from_rx(cols.end));
+            let removed = row[start..end].to_owned();
+            if !removed.is_empty() {
+                self.new_diff(EditDiff::Remove(end, y, removed.clone()));
+            }
+            deleted.push(removed);
+        }
+        deleted
+    }
+
+    // Insert text at the display column of the line. The line is padded with spaces when it is
+    // shorter than the column. Returns the index of character after the inserted text.
+    fn insert_at_column(&mut self, y: usize, col: usize, text: &str) -> usize {
+        if text.is_empty() {
+            return self.row[y].cx_from_rx(col);
+        }
+        let width = self.row[y].rx_from_cx(self.row[y].len());
+        if width < col {
+            self.new_diff(EditDiff::Append(y, " ".repeat(col - width)));
+        }
+        let x = self.row[y].cx_from_rx(col);
+        self.new_diff(EditDiff::Insert(x, y, text.to_owned()));
+        x + text.chars().count()
+    }
+
+    // Remove the rectangle between mark and cursor. Returns the removed text of each line or None
+    // when mark is not set.
+    pub fn kill_rectangle(&mut self) -> Option<Vec<String>> {
+        let (lines, cols) = self.take_region_rectangle()?;
+        self.insert_undo_point();
+        let cy = self.cy;
+        let killed = self.delete_rectangle(lines, &cols);
+        let x = self
+            .row
+            .get(cy)
+            .map(|r| r.cx_from_rx(cols.start))
+            .unwrap_or(0);
+        self.set_cursor(x, cy);
+        Some(killed)
+    }
+
+    // Insert each line of the rectangle at the cursor column of successive lines. The cursor
+    // moves to the end of the last inserted text.
+    pub fn yank_rectangle(&mut self, rect: &[String]) {
+        if rect.is_empty() {
+            return;
+        }
+        self.insert_undo_point();
+        let (cx, cy) = (self.cx, self.cy);
+        let col = self
+            .row
+            .get(cy)
+            .map(|r| r.rx_from_cx(cmp::min(cx, r.len())))
+            .unwrap_or(0);
+        let mut x = cx;
+        for (i, text) in rect.iter().enumerate() {
+            let y = cy + i;
+            if y >= self.row.len() {
+                self.new_diff(EditDiff::InsertLine(y, "".to_string()));
+            }
+            x = self.insert_at_column(y, col, text);
+        }
+        self.set_cursor(x, cy + rect.len() - 1);
+    }
+
+    // Insert text at the left edge of rectangle on each line. When `replace` is true, the text
+    // replaces the rectangle. Returns false when mark is not set.
+    pub fn insert_rectangle_text(&mut self, text: &str, replace: bool) -> bool {
+        let (lines, cols) = match self.take_region_rectangle() {
+            Some(rect) => rect,
+            None => return false,
+        };
+        self.insert_undo_point();
+        let (cx, cy) = (self.cx, self.cy);
+        if replace {
+            self.delete_rectangle(lines.clone(), &cols);
+        }
+        let mut new_cx = cx;
+        for y in lines {
+            let x = self.insert_at_column(y, cols.start, text);
+            if y == cy {
+                new_cx = x;
+            }
+        }
+        self.set_cursor(new_cx, cy);
+        true
+    }
+
     pub fn rows(&self) -> &[Row] {
         &self.row
     }
@@ -484,9 +1828,15 @@ impl TextBuffer {
         Lines(self.row.iter())
     }
 
+    // Give a path to the buffer. Language, file format and indentation are updated for the path as
+    // open() does
     pub fn set_file<S: Into<String>>(&mut self, file_path: S) {
         let file = FilePath::from_string(file_path);
         self.lang = Language::detect(&file.path);
+        let config = EditorConfig::load(&file.path);
+        self.format = config.file_format();
+        self.editorconfig_indent = config.indent_setting(self.lang.indent());
+        self.set_indent_setting(self.editorconfig_indent);
         self.file = Some(file);
     }
 
@@ -498,8 +1848,50 @@ impl TextBuffer {
         self.lang = lang;
     }
 
+    pub fn tab_stop(&self) -> usize {
+        self.tab_stop
+    }
+
+    pub fn set_tab_stop(&mut self, tab_stop: usize) {
+        self.tab_stop = tab_stop;
+        let mut rendered = false;
+        for row in self.row.iter_mut() {
+            rendered |= row.set_tab_stop(tab_stop);
+        }
+        if rendered {
+            self.set_dirty_start(0);
+        }
+    }
+
+    pub fn indent(&self) -> Indent {
+        self.indent
+            .or(self.detected_indent)
+            .unwrap_or_else(|| self.lang.indent())
+    }
+
+    pub fn set_indent(&mut self, indent: Indent) {
+        self.indent = Some(indent);
+    }
+
+    // Apply indentation configured by user. Settings given by .editorconfig take precedence
+    pub fn set_default_indent_setting(&mut self, setting: IndentSetting) {
+        self.set_indent_setting(setting.merge(self.editorconfig_indent));
+    }
+
+    pub fn set_indent_setting(&mut self, setting: IndentSetting) {
+        if let Some(indent) = setting.indent {
+            self.set_indent(indent);
+        }
+        if let Some(tab_stop) = setting.tab_stop {
+            self.set_tab_stop(tab_stop);
+        }
+    }
+
     pub fn save(&mut self) -> std::result::Result<String, String> {
         self.insert_undo_point();
+        if self.format.trim_trailing_whitespace {
+            self.trim_trailing_whitespaces();
+        }
 
         let file = if let Some(file) = &self.file {
             file
@@ -507,23 +1899,75 @@ impl TextBuffer {
             return Ok("".to_string()); // Canceled
         };
 
-        let f = match File::create(&file.path) {
+        let mut text = String::new();
+        let eol = self.format.line_ending.as_str();
+        for line in self.row.iter() {
+            text.push_str(line.buffer());
+            text.push_str(eol);
+        }
+        if !self.format.final_newline && text.ends_with(eol) {
+            text.truncate(text.len() - eol.len());
+        }
+        let charset = self.format.charset;
+        let bytes = charset.encode(&text).map_err(|c| {
+            format!(
+                "Could not save: {:?} cannot be encoded in {}",
+                c,
+                charset.name()
+            )
+        })?;
+
+        let mut f = match File::create(&file.path) {
             Ok(f) => f,
             Err(e) => return Err(format!("Could not save: {}", e)),
         };
-        let mut f = io::BufWriter::new(f);
-        let mut bytes = 0;
-        for line in self.row.iter() {
-            let b = line.buffer();
-            writeln!(f, "{}", b).map_err(|e| format!("Could not write to file: {}", e))?;
-            bytes += b.as_bytes().len() + 1;
-        }
+        f.write_all(&bytes)
+            .map_err(|e| format!("Could not write to file: {}", e))?;
         f.flush()
             .map_err(|e| format!("Could not flush to file: {}", e))?;
 
         self.undo_count = 0;
         self.modified = false;
-        Ok(format!("{} bytes written to {}", bytes, &file.display))
+        Ok(format!(
+            "{} bytes written to {}",
+            bytes.len(),
+            &file.display
+        ))
+    }
+
+    // Remove whitespaces at end of all lines. Cursors in the removed whitespaces are moved to end
+    // of line
+    fn trim_trailing_whitespaces(&mut self) {
+        let (cx, cy) = (self.cx, self.cy);
+        for y in 0..self.row.len() {
+            let line = self.row[y].buffer();
+            let trimmed = line.trim_end().len();
+            if trimmed < line.len() {
+                let removed = line[trimmed..].to_owned();
+                self.new_diff(EditDiff::Truncate(y, removed));
+            }
+        }
+
+        let clamp = |rows: &[Row], (x, y): (usize, usize)| match rows.get(y) {
+            Some(row) => (cmp::min(x, row.len()), y),
+            None => (x, y),
+        };
+        let (cx, cy) = clamp(&self.row, (cx, cy));
+        self.set_cursor(cx, cy);
+        for c in self.cursors.iter_mut() {
+            *c = clamp(&self.row, *c);
+        }
+        self.cursors.dedup();
+        self.cursors.retain(|&c| c != (cx, cy));
+    }
+
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn toggle_overwrite(&mut self) -> bool {
+        self.overwrite = !self.overwrite;
+        self.overwrite
     }
 
     pub fn set_cursor(&mut self, x: usize, y: usize) {
@@ -538,6 +1982,7 @@ impl TextBuffer {
     fn after_undoredo(&mut self, state: Option<(usize, usize, usize, bool)>) -> bool {
         match state {
             Some((x, y, s, _)) => {
+                self.cursors.clear(); // Secondary cursors may not be valid after undo/redo
                 self.set_cursor(x, y);
                 self.set_dirty_start(s);
                 true
@@ -547,7 +1992,7 @@ impl TextBuffer {
     }
 
     pub fn undo(&mut self) -> bool {
-        let state = self.history.undo(&mut self.row);
+        let state = self.history.undo(&mut self.row, self.tab_stop);
         if let Some((_, _, _, edited)) = state {
             // If edited is true, it means that undo target is the ongoing change. In the case,
             // undo point is not consumed and undo count should not be decreased
@@ -560,7 +2005,7 @@ impl TextBuffer {
     }
 
     pub fn redo(&mut self) -> bool {
-        let state = self.history.redo(&mut self.row);
+        let state = self.history.redo(&mut self.row, self.tab_stop);
         if let Some((_, _, _, edited)) = state {
             // If edited is true, it means that redo target is the ongoing change. In the case,
             // redo does not happen since the new ongoing change is happening and undo count should